@@ -1,21 +1,51 @@
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::{api::caller, call, trap};
-use ic_cdk::storage::{stable_restore, stable_save};
+use ic_cdk::{api::{caller, time}, call};
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
-use serde::{Deserialize as SerdeDeserialize, Serialize};
-use serde_json::json;
+use icroots_events::{CollateralEvent, Event, EventKind, Payload};
+use icroots_state::{candid_storable, decode, encode, Persisted, Versioned};
+use icroots_valuation::{CollateralNeeds, PriceStatus, Valuation};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 
+/// Persisted layout (see `icroots_state`).
+/// 0: the first deployed `State` (balances only);
+/// 1: balances and locks in stable maps
+const STATE_VERSION: u32 = 1;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const STATE_MEMORY: MemoryId = MemoryId::new(0);
+const BALANCES_MEMORY: MemoryId = MemoryId::new(1);
+const LOCKS_MEMORY: MemoryId = MemoryId::new(2);
+const OWNER_LOCKS_MEMORY: MemoryId = MemoryId::new(3);
+
+/// Small settings kept on the heap; balances and locks live in stable maps
 #[derive(CandidType, Deserialize, Clone, Debug)]
 struct State {
     admin: Principal,
     /// Principals allowed to deposit on behalf of users (e.g., loans canister)
     allowed_depositors: HashSet<Principal>,
-    /// Mock balances by user principal (we use u128 for nat)
-    balances: HashMap<Principal, u128>,
+    /// Loans canister, the only caller allowed to lock/release
+    loans: Option<Principal>,
     /// Optional event bus for audit logs
     event_bus: Option<Principal>,
+    /// Optional BTC/USD price feed used to value balances
+    oracle: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Lock {
+    owner: Principal,
+    amount: u128,
+    locked_at_ns: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct CollateralAccount {
+    free: u128,
+    locked: u128,
 }
 
 impl Default for State {
@@ -23,14 +53,102 @@ impl Default for State {
         Self {
             admin: Principal::anonymous(),
             allowed_depositors: HashSet::new(),
-            balances: HashMap::new(),
+            loans: None,
             event_bus: None,
+            oracle: None,
         }
     }
 }
 
+/// Failure reasons returned by updates
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+enum Error {
+    Unauthorized(String),
+    InvalidAmount(String),
+    InsufficientCollateral { free: u128, locked: u128 },
+    /// `withdraw` would dip into free collateral that under-collateralised
+    /// loans need to stay healthy
+    ReservedForLoans { free: u128, reserved: u128 },
+    /// `lock` was called twice for the same loan id
+    AlreadyLocked,
+    LockNotFound,
+    DependencyUnavailable(String),
+}
+
+/// Version 0 `State`, as `stable_save`d by the first deployed build
+#[derive(CandidType, Deserialize)]
+struct StateV0 {
+    admin: Principal,
+    allowed_depositors: HashSet<Principal>,
+    balances: HashMap<Principal, u128>,
+    event_bus: Option<Principal>,
+}
+
+candid_storable!(Lock);
+
 thread_local! {
     static STATE: RefCell<State> = RefCell::new(State::default());
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+    /// `State` as of the last upgrade
+    static SAVED_STATE: RefCell<StableCell<Persisted, Memory>> = RefCell::new(
+        StableCell::init(memory(STATE_MEMORY), Persisted::new(&State::default()))
+            .expect("init state cell"),
+    );
+    /// Free (unlocked) mock balances by user principal (we use u128 for nat)
+    static BALANCES: RefCell<StableBTreeMap<Principal, u128, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(BALANCES_MEMORY)));
+    /// Collateral locked against a single loan, keyed by loan id
+    static LOCKS: RefCell<StableBTreeMap<u128, Lock, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(LOCKS_MEMORY)));
+    /// (owner, loan id) index of `LOCKS`
+    static OWNER_LOCKS: RefCell<StableBTreeMap<(Principal, u128), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(OWNER_LOCKS_MEMORY)));
+}
+
+fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+fn balance_of(p: Principal) -> u128 {
+    BALANCES.with(|b| b.borrow().get(&p).unwrap_or(0))
+}
+
+fn set_balance(p: Principal, amount: u128) {
+    BALANCES.with(|b| {
+        if amount == 0 {
+            b.borrow_mut().remove(&p);
+        } else {
+            b.borrow_mut().insert(p, amount);
+        }
+    });
+}
+
+fn insert_lock(loan_id: u128, lock: Lock) {
+    OWNER_LOCKS.with(|m| m.borrow_mut().insert((lock.owner, loan_id), ()));
+    LOCKS.with(|m| m.borrow_mut().insert(loan_id, lock));
+}
+
+fn remove_lock(loan_id: u128) -> Option<Lock> {
+    let lock = LOCKS.with(|m| m.borrow_mut().remove(&loan_id))?;
+    OWNER_LOCKS.with(|m| m.borrow_mut().remove(&(lock.owner, loan_id)));
+    Some(lock)
+}
+
+/// v0 → v1: balances move to a stable map and there are no locks yet. The
+/// baseline didn't record the loans canister, so locking stays closed until
+/// the admin names it with `set_loans`.
+fn migrate_v0(old: StateV0) -> State {
+    for (p, amount) in old.balances {
+        set_balance(p, amount);
+    }
+    State {
+        admin: old.admin,
+        loans: None,
+        allowed_depositors: old.allowed_depositors,
+        event_bus: old.event_bus,
+        oracle: None,
+    }
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug, Default)]
@@ -41,6 +159,8 @@ struct InitArgs {
     loans: Option<Principal>,
     /// Optional event bus canister id
     event_bus: Option<Principal>,
+    /// Optional oracle canister id
+    oracle: Option<Principal>,
 }
 
 #[init]
@@ -53,74 +173,425 @@ fn init(args: Option<InitArgs>) {
         if let Some(l) = args.loans {
             st.allowed_depositors.insert(l);
         }
+        st.loans = args.loans;
         st.event_bus = args.event_bus;
+        st.oracle = args.oracle;
     });
 }
 
+/// Balances and locks live in stable memory already; only `State` is saved
 #[pre_upgrade]
 fn pre_upgrade() {
-    let st = STATE.with(|s| s.borrow().clone());
-    stable_save((st,)).expect("stable_save failed");
+    STATE.with(|s| SAVED_STATE.with(|c| icroots_state::save(c, &*s.borrow())));
 }
 
 #[post_upgrade]
 fn post_upgrade() {
-    let (st,): (State,) = stable_restore().unwrap_or((
-        State { admin: caller(), ..State::default() },
-    ));
+    let st = icroots_state::restore_or_trap(|| SAVED_STATE.with(|c| c.borrow().get().clone()));
     STATE.with(|s| *s.borrow_mut() = st);
 }
 
+impl Versioned for State {
+    const VERSION: u32 = STATE_VERSION;
+
+    fn migrate_step(from: u32, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        match from {
+            0 => encode(&migrate_v0(decode(bytes)?)),
+            _ => Err(format!("no migration from state version {from}")),
+        }
+    }
+}
+
+/// Total collateral (free + locked)
 #[query]
 fn get_collateral(p: Principal) -> u128 {
-    STATE.with(|s| *s.borrow().balances.get(&p).unwrap_or(&0))
+    let acct = get_account(p);
+    acct.free.saturating_add(acct.locked)
+}
+
+#[query]
+fn get_account(p: Principal) -> CollateralAccount {
+    CollateralAccount { free: balance_of(p), locked: locked_by(p) }
+}
+
+/// Balances valued in USD (6 decimals) at the oracle price
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct CollateralValuation {
+    free: u128,
+    locked: u128,
+    price: Option<PriceStatus>,
+    free_value: Option<u128>,
+    locked_value: Option<u128>,
+}
+
+// update (not query) because we call the oracle
+#[update]
+async fn get_valuation(p: Principal) -> Result<CollateralValuation, Error> {
+    let acct = get_account(p);
+    let price = match STATE.with(|s| s.borrow().oracle) {
+        Some(oracle) => fetch_price(oracle).await?,
+        None => None,
+    };
+    // stale prices are reported but not used for valuation
+    let value = |units: u128| {
+        price
+            .as_ref()
+            .filter(|p| !p.stale)
+            .map(|p| Valuation { price_e6: Some(p.price_e6) }.value_of(units))
+    };
+    Ok(CollateralValuation {
+        free_value: value(acct.free),
+        locked_value: value(acct.locked),
+        free: acct.free,
+        locked: acct.locked,
+        price,
+    })
+}
+
+async fn fetch_price(oracle: Principal) -> Result<Option<PriceStatus>, Error> {
+    let res: Result<(Option<PriceStatus>,), _> = call(oracle, "get_price", ()).await;
+    res.map(|(price,)| price)
+        .map_err(|(_, msg)| Error::DependencyUnavailable(format!("oracle call failed: {msg}")))
+}
+
+fn locked_by(p: Principal) -> u128 {
+    let loan_ids: Vec<u128> = OWNER_LOCKS.with(|m| {
+        m.borrow().range((p, 0)..=(p, u128::MAX)).map(|((_, id), _)| id).collect()
+    });
+    LOCKS.with(|m| {
+        let locks = m.borrow();
+        loan_ids.iter().filter_map(|id| locks.get(id)).map(|l| l.amount).sum()
+    })
 }
 
+/// Move `amount` of `owner`'s free collateral into a lock for `loan_id`
 #[update]
-async fn deposit_mock(p: Principal, amount: u128) {
-    ensure_can_deposit().unwrap_or_else(|e| trap(&e));
+async fn lock(owner: Principal, loan_id: u128, amount: u128) -> Result<(), Error> {
+    ensure_loans_canister()?;
+    lock_free(owner, loan_id, amount, time())?;
+
+    let details = CollateralEvent::Locked { loan_id, amount };
+    emit_event(EventKind::CollateralLocked, owner, details).await;
+    Ok(())
+}
+
+fn lock_free(owner: Principal, loan_id: u128, amount: u128, now: u64) -> Result<(), Error> {
     if amount == 0 {
-        trap("amount must be > 0");
+        return Err(Error::InvalidAmount("amount must be > 0".into()));
+    }
+    if LOCKS.with(|m| m.borrow().contains_key(&loan_id)) {
+        return Err(Error::AlreadyLocked);
+    }
+    let free = balance_of(owner);
+    if free < amount {
+        return Err(Error::InsufficientCollateral { free, locked: locked_by(owner) });
     }
+    set_balance(owner, free - amount);
+    insert_lock(loan_id, Lock { owner, amount, locked_at_ns: now });
+    Ok(())
+}
+
+/// Return the collateral locked for `loan_id` to its owner's free balance.
+/// Releasing an unknown loan is a no-op (returns 0) so retries are safe.
+#[update]
+async fn release(loan_id: u128) -> Result<u128, Error> {
+    ensure_loans_canister()?;
+
+    let Some(lock) = release_lock(loan_id) else {
+        return Ok(0);
+    };
+
+    let details = CollateralEvent::Released { loan_id, amount: lock.amount };
+    emit_event(EventKind::CollateralReleased, lock.owner, details).await;
+    Ok(lock.amount)
+}
+
+fn release_lock(loan_id: u128) -> Option<Lock> {
+    let lock = remove_lock(loan_id)?;
+    set_balance(lock.owner, balance_of(lock.owner).saturating_add(lock.amount));
+    Some(lock)
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct SeizeResult {
+    /// Moved to the recipient's free balance
+    seized: u128,
+    /// Remainder of the lock returned to the owner's free balance
+    returned: u128,
+}
+
+/// Liquidation: move up to `amount` of the lock for `loan_id` to `to`, and
+/// return whatever is left of the lock to its owner.
+#[update]
+async fn seize(loan_id: u128, amount: u128, to: Principal) -> Result<SeizeResult, Error> {
+    ensure_loans_canister()?;
+    let (owner, SeizeResult { seized, returned }) = seize_lock(loan_id, amount, to)?;
+
+    emit_event(
+        EventKind::CollateralSeized,
+        owner,
+        CollateralEvent::Seized { loan_id, to, seized, returned },
+    )
+    .await;
 
+    Ok(SeizeResult { seized, returned })
+}
+
+/// Split the lock for `loan_id` between `to` and its owner; returns the owner
+fn seize_lock(loan_id: u128, amount: u128, to: Principal) -> Result<(Principal, SeizeResult), Error> {
+    let lock = remove_lock(loan_id).ok_or(Error::LockNotFound)?;
+    let seized = amount.min(lock.amount);
+    let returned = lock.amount - seized;
+    for (p, amt) in [(to, seized), (lock.owner, returned)] {
+        if amt > 0 {
+            set_balance(p, balance_of(p).saturating_add(amt));
+        }
+    }
+    Ok((lock.owner, SeizeResult { seized, returned }))
+}
+
+// best-effort audit event about `owner`'s collateral
+async fn emit_event(kind: EventKind, owner: Principal, details: CollateralEvent) {
+    if let Some(bus) = STATE.with(|s| s.borrow().event_bus) {
+        let event = Event::new(kind, caller(), Some(owner), Payload::Collateral(details));
+        icroots_events::emit(bus, event).await;
+    }
+}
+
+#[update]
+async fn deposit_mock(p: Principal, amount: u128) -> Result<(), Error> {
+    ensure_can_deposit()?;
+    if amount == 0 {
+        return Err(Error::InvalidAmount("amount must be > 0".into()));
+    }
+
+    let balance = balance_of(p)
+        .checked_add(amount)
+        .ok_or_else(|| Error::InvalidAmount("overflow on deposit".into()))?;
+    set_balance(p, balance);
+
+    emit_event(EventKind::CollateralDeposited, p, CollateralEvent::Deposited { amount }).await;
+    Ok(())
+}
+
+/// Name the loans canister: the only caller allowed to lock/release/seize,
+/// and a depositor
+#[update]
+fn set_loans(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    use_loans(p);
+    Ok(())
+}
+
+fn use_loans(p: Principal) {
     STATE.with(|s| {
         let mut st = s.borrow_mut();
-        let entry = st.balances.entry(p).or_insert(0);
-        *entry = entry.checked_add(amount).unwrap_or_else(|| trap("overflow on deposit"));
+        st.allowed_depositors.insert(p);
+        st.loans = Some(p);
     });
+}
 
-    // best-effort audit event
-    if let Some(bus) = STATE.with(|s| s.borrow().event_bus) {
-        let payload = json!({
-            "kind": "collateral.deposit_mock",
-            "actor": format!("{}", caller()),
-            "principal": format!("{}", p),
-            "amount": amount,
-        }).to_string();
-        let _: Result<(), _> = call(bus, "emit", (payload,)).await;
+/// Withdraw the caller's own free collateral. Collateral locked for active
+/// loans stays put until the loans canister releases it, and free collateral
+/// that under-collateralised loans need (at the oracle price) stays too.
+#[update]
+async fn withdraw(amount: u128) -> Result<CollateralAccount, Error> {
+    if amount == 0 {
+        return Err(Error::InvalidAmount("amount must be > 0".into()));
     }
+    let me = caller();
+
+    let reserved = reserved_for_loans(me).await?;
+    let account = debit_free(me, amount, reserved)?;
+
+    emit_event(EventKind::CollateralWithdrawn, me, CollateralEvent::Withdrawn { amount }).await;
+
+    Ok(account)
+}
+
+/// Take `amount` from `owner`'s free balance, keeping `reserved` of it
+fn debit_free(owner: Principal, amount: u128, reserved: u128) -> Result<CollateralAccount, Error> {
+    let (free, locked) = (balance_of(owner), locked_by(owner));
+    if free < amount {
+        return Err(Error::InsufficientCollateral { free, locked });
+    }
+    if free - amount < reserved {
+        return Err(Error::ReservedForLoans { free, reserved });
+    }
+    set_balance(owner, free - amount);
+    Ok(CollateralAccount { free: free - amount, locked })
+}
+
+/// Free units `owner`'s open loans need on top of their locks. Asks the loans
+/// canister for the debts; fails rather than guessing when there is no fresh price.
+async fn reserved_for_loans(owner: Principal) -> Result<u128, Error> {
+    let (loans, oracle) = STATE.with(|s| {
+        let st = s.borrow();
+        (st.loans, st.oracle)
+    });
+    let Some(loans) = loans else {
+        return Ok(0);
+    };
+    let res: Result<(CollateralNeeds,), _> = call(loans, "get_collateral_needs", (owner,)).await;
+    let (needs,) = res
+        .map_err(|(_, msg)| Error::DependencyUnavailable(format!("loans call failed: {msg}")))?;
+    if needs.debts.is_empty() {
+        return Ok(0);
+    }
+    let price_e6 = match oracle {
+        Some(oracle) => match fetch_price(oracle).await? {
+            Some(p) if !p.stale => Some(p.price_e6),
+            _ => {
+                return Err(Error::DependencyUnavailable(
+                    "no fresh price to check loan health".into(),
+                ))
+            }
+        },
+        // like loans_backend, collateral is valued 1:1 without an oracle
+        None => None,
+    };
+    Ok(reserved_units(&needs, Valuation { price_e6 }))
+}
+
+/// Units each loan needs at `valuation` to stay at the liquidation threshold,
+/// minus what is already locked for it, summed over the loans
+fn reserved_units(needs: &CollateralNeeds, valuation: Valuation) -> u128 {
+    needs
+        .debts
+        .iter()
+        .map(|d| {
+            let locked = LOCKS.with(|m| m.borrow().get(&d.loan_id)).map_or(0, |l| l.amount);
+            needs.units_needed(d.debt, valuation).saturating_sub(locked)
+        })
+        .sum()
+}
+
+fn ensure_admin() -> Result<(), Error> {
+    let c = caller();
+    STATE.with(|s| {
+        if c == s.borrow().admin {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized("caller is not admin".into()))
+        }
+    })
 }
 
-fn ensure_can_deposit() -> Result<(), String> {
+fn ensure_can_deposit() -> Result<(), Error> {
     let c = caller();
     STATE.with(|s| {
         let st = s.borrow();
         if c == st.admin || st.allowed_depositors.contains(&c) {
             Ok(())
         } else {
-            Err("unauthorized: caller is not admin or allowed depositor".into())
+            Err(Error::Unauthorized("caller is not admin or allowed depositor".into()))
         }
     })
 }
 
+fn ensure_loans_canister() -> Result<(), Error> {
+    let c = caller();
+    STATE.with(|s| {
+        if s.borrow().loans == Some(c) {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized("caller is not the loans canister".into()))
+        }
+    })
+}
+
+ic_cdk::export_candid!();
+
 // -------------- (optional) basic tests --------------
 #[cfg(test)]
 mod tests {
     use super::*;
+    use icroots_valuation::{LoanDebt, SATS_PER_BTC};
+
+    #[test]
+    fn did_file_matches_the_rust_interface() {
+        use candid_parser::utils::{service_equal, CandidSource};
+        let did =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("collateral_backend.did");
+        service_equal(CandidSource::Text(&__export_service()), CandidSource::File(&did))
+            .unwrap_or_else(|e| panic!("collateral_backend.did is out of date: {e}"));
+    }
+
     #[test]
     fn defaults_to_zero() {
         let me = Principal::anonymous();
         STATE.with(|s| *s.borrow_mut() = State { admin: me, ..State::default() });
         assert_eq!(get_collateral(me), 0);
     }
+
+    #[test]
+    fn locks_move_collateral_between_free_and_locked() {
+        let (owner, liquidator) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+        set_balance(owner, 1_000);
+
+        lock_free(owner, 7, 600, 0).unwrap();
+        assert_eq!(lock_free(owner, 7, 1, 0), Err(Error::AlreadyLocked));
+        let err = lock_free(owner, 8, 500, 0);
+        assert_eq!(err, Err(Error::InsufficientCollateral { free: 400, locked: 600 }));
+        lock_free(owner, 8, 300, 0).unwrap();
+        let acct = get_account(owner);
+        assert_eq!((acct.free, acct.locked), (100, 900));
+
+        // seize splits a lock; release returns one whole; both are one-shot
+        let (seized_from, seized) = seize_lock(7, 250, liquidator).unwrap();
+        assert_eq!((seized_from, seized.seized, seized.returned), (owner, 250, 350));
+        assert!(matches!(seize_lock(7, 1, liquidator), Err(Error::LockNotFound)));
+        assert_eq!(release_lock(8).map(|l| l.amount), Some(300));
+        assert!(release_lock(8).is_none());
+        let acct = get_account(owner);
+        assert_eq!((acct.free, acct.locked, balance_of(liquidator)), (750, 0, 250));
+    }
+
+    #[test]
+    fn withdraw_keeps_what_unhealthy_loans_need() {
+        let owner = Principal::from_slice(&[1]);
+        set_balance(owner, 1_000);
+        lock_free(owner, 7, 500, 0).unwrap();
+        let needs = CollateralNeeds {
+            debts: vec![LoanDebt { loan_id: 7, debt: 240 }],
+            liquidation_threshold_bps: 8_000,
+        };
+
+        // at par (one unit worth one) 500 locked units cover the 300 needed
+        let par = SATS_PER_BTC as u64;
+        assert_eq!(reserved_units(&needs, Valuation { price_e6: Some(par) }), 0);
+        // at half that price the loan needs 600 units: 100 free ones are kept
+        let reserved = reserved_units(&needs, Valuation { price_e6: Some(par / 2) });
+        assert_eq!(reserved, 100);
+
+        let err = debit_free(owner, 450, reserved).err();
+        assert_eq!(err, Some(Error::ReservedForLoans { free: 500, reserved: 100 }));
+        let acct = debit_free(owner, 400, reserved).unwrap();
+        assert_eq!((acct.free, acct.locked), (100, 500));
+        let err = debit_free(owner, 101, 0).err();
+        assert_eq!(err, Some(Error::InsufficientCollateral { free: 100, locked: 500 }));
+    }
+
+    #[test]
+    fn baseline_snapshot_migrates_to_current_version() {
+        let st: State =
+            icroots_state::restore_snapshot(include_bytes!("../snapshots/state_v0.bin")).unwrap();
+
+        let p = |i: u8| Principal::from_slice(&[i]);
+        assert_eq!((st.admin, st.loans, st.event_bus), (p(0xad), None, Some(p(0xeb))));
+        assert!(st.allowed_depositors.contains(&p(0xaa)));
+        let acct = get_account(p(1));
+        assert_eq!((acct.free, acct.locked), (5_000, 0));
+        assert_eq!(get_collateral(p(2)), 300);
+
+        // the admin names the loans canister after the upgrade
+        STATE.with(|s| *s.borrow_mut() = st);
+        use_loans(p(0xaa));
+
+        // pre_upgrade + post_upgrade: only the small State goes through the cell
+        pre_upgrade();
+        let saved = SAVED_STATE.with(|c| c.borrow().get().clone());
+        assert_eq!(saved.restore::<State>().unwrap().loans, Some(p(0xaa)));
+        assert_eq!(get_account(p(1)).free, 5_000);
+    }
 }
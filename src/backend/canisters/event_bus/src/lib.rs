@@ -1,31 +1,659 @@
-use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::{caller, time};
+use ic_cdk::api::stable::stable_size;
+use ic_cdk::trap;
+use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use icroots_events::{Event, EventKind};
+use icroots_state::{candid_storable, Persisted, Versioned};
 use std::cell::RefCell;
-use std::collections::VecDeque;
+use std::collections::HashSet;
 
-const MAX_EVENTS: usize = 200;
+/// Persisted layout (see `icroots_state`). The baseline bus kept nothing in
+/// stable memory; 1 is the first layout that does.
+const STATE_VERSION: u32 = 1;
+/// Most events returned by one `get_events` / `list_since` / `find_events` call
+const MAX_PAGE: u64 = 500;
+/// Most index entries one `find_events` call looks at before handing back a cursor
+const MAX_SCAN: usize = 2_000;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const STATE_MEMORY: MemoryId = MemoryId::new(0);
+const EVENTS_MEMORY: MemoryId = MemoryId::new(1);
+const BY_KIND_MEMORY: MemoryId = MemoryId::new(2);
+const BY_ACTOR_MEMORY: MemoryId = MemoryId::new(3);
+const BY_SUBJECT_MEMORY: MemoryId = MemoryId::new(4);
+
+/// Retention (tunable at deploy/init time and by the admin)
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Config {
+    /// Events kept; the oldest are dropped first
+    max_events: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self { max_events: 10_000 }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct State {
+    admin: Principal,
+    cfg: Config,
+    /// Principals allowed to call `emit` (the other ICRoots canisters)
+    emitters: HashSet<Principal>,
+    /// `emit` calls refused because the caller isn't an emitter
+    rejected_emits: u64,
+    /// Sequence number of the next event; never reused, even after trimming
+    next_seq: u64,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            admin: Principal::anonymous(),
+            cfg: Config::default(),
+            emitters: HashSet::new(),
+            rejected_emits: 0,
+            next_seq: 0,
+        }
+    }
+}
+
+/// An event as logged by the bus
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct LoggedEvent {
+    /// Position in the log, increasing by one per accepted event
+    seq: u64,
+    /// When the bus accepted it
+    received_ns: u64,
+    event: Event,
+}
+
+/// What `find_events` matches on; unset fields match everything
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct EventFilter {
+    /// Prefix of the kind's dotted name, e.g. "loans." or "collateral.seize"
+    kind_prefix: Option<String>,
+    actor: Option<Principal>,
+    subject: Option<Principal>,
+    /// Received at or after (inclusive)
+    from_ns: Option<u64>,
+    /// Received before (exclusive)
+    to_ns: Option<u64>,
+}
+
+/// One page of `find_events`
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct EventPage {
+    events: Vec<LoggedEvent>,
+    /// Pass as `start_seq` to continue; None once the log is exhausted
+    next_seq: Option<u64>,
+}
+
+candid_storable!(LoggedEvent);
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct InitArgs {
+    /// Optional admin override; defaults to deployer
+    admin: Option<Principal>,
+    max_events: Option<u64>,
+    /// ICRoots canisters allowed to emit
+    loans: Option<Principal>,
+    collateral: Option<Principal>,
+    repute: Option<Principal>,
+    trust_ai: Option<Principal>,
+}
+
+impl InitArgs {
+    /// Peer canisters named in the args; they may emit
+    fn peers(&self) -> impl Iterator<Item = Principal> {
+        [self.loans, self.collateral, self.repute, self.trust_ai].into_iter().flatten()
+    }
+}
+
+/// Emit counters, for monitoring
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct Stats {
+    events: u64,
+    rejected_emits: u64,
+    /// Oldest retained seq, if any
+    first_seq: Option<u64>,
+    /// Seq the next accepted event will get
+    next_seq: u64,
+}
+
+/// Failure reasons returned by updates
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+enum Error {
+    Unauthorized(String),
+    InvalidArgument(String),
+}
 
 thread_local! {
-    static EVENTS: RefCell<VecDeque<String>> = RefCell::new(VecDeque::new());
+    static STATE: RefCell<State> = RefCell::new(State::default());
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+    /// `State` as of the last upgrade
+    static SAVED_STATE: RefCell<StableCell<Persisted, Memory>> = RefCell::new(
+        StableCell::init(memory(STATE_MEMORY), Persisted::new(&State::default()))
+            .expect("init state cell"),
+    );
+    /// Events by seq, oldest first
+    static EVENTS: RefCell<StableBTreeMap<u64, LoggedEvent, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(EVENTS_MEMORY)));
+    /// (position in `EventKind::ALL`, seq)
+    static BY_KIND: RefCell<StableBTreeMap<(u8, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(BY_KIND_MEMORY)));
+    static BY_ACTOR: RefCell<StableBTreeMap<(Principal, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(BY_ACTOR_MEMORY)));
+    static BY_SUBJECT: RefCell<StableBTreeMap<(Principal, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(BY_SUBJECT_MEMORY)));
+}
+
+fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+#[init]
+fn init(args: Option<InitArgs>) {
+    let me = caller();
+    let args = args.unwrap_or_default();
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        st.admin = args.admin.unwrap_or(me);
+        if let Some(v) = args.max_events { st.cfg.max_events = v; }
+        st.emitters.extend(args.peers());
+        validate_config(&st.cfg).unwrap_or_else(|e| trap(&e));
+    });
+}
+
+fn validate_config(cfg: &Config) -> Result<(), String> {
+    if cfg.max_events == 0 {
+        return Err("max_events must be > 0".into());
+    }
+    Ok(())
 }
 
-/// Append an event (keeps only the latest MAX_EVENTS)
+#[pre_upgrade]
+fn pre_upgrade() {
+    STATE.with(|s| SAVED_STATE.with(|c| icroots_state::save(c, &*s.borrow())));
+}
+
+/// Only the peers in the upgrade args are used: they join the allowlist, so a
+/// bus upgraded from the baseline build, which had none, keeps accepting its
+/// producers.
+#[post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    let args = args.unwrap_or_default();
+    // the baseline build kept nothing in stable memory
+    let st = if stable_size() == 0 {
+        baseline_state(caller(), &args).unwrap_or_else(|e| trap(&e))
+    } else {
+        icroots_state::restore_or_trap(|| SAVED_STATE.with(|c| c.borrow().get().clone()))
+    };
+    STATE.with(|s| *s.borrow_mut() = st);
+    allow_peers(&args);
+}
+
+/// Fresh `State` for a bus upgraded from the baseline build. That build had
+/// no allowlist, so the upgrade is refused unless the args name the peers;
+/// otherwise every emit would be rejected from then on.
+fn baseline_state(upgrader: Principal, args: &InitArgs) -> Result<State, String> {
+    if args.peers().next().is_none() {
+        return Err("upgrading from the baseline bus needs the peer canisters \
+                    (loans, collateral, repute, trust_ai) in the upgrade args"
+            .into());
+    }
+    Ok(State { admin: args.admin.unwrap_or(upgrader), ..State::default() })
+}
+
+fn allow_peers(args: &InitArgs) {
+    STATE.with(|s| s.borrow_mut().emitters.extend(args.peers()));
+}
+
+impl Versioned for State {
+    const VERSION: u32 = STATE_VERSION;
+
+    fn migrate_step(from: u32, _bytes: &[u8]) -> Result<Vec<u8>, String> {
+        Err(format!("no migration from state version {from}"))
+    }
+}
+
+/// Append an event from an allowed emitter (keeps only the latest `max_events`)
 #[update]
-fn emit(event: String) {
+fn emit(event: Event) -> Result<(), Error> {
+    record_emit(caller(), time(), event)
+}
+
+/// The caller is stamped as `emitter`, whatever the producer put there
+fn record_emit(from: Principal, now: u64, mut event: Event) -> Result<(), Error> {
+    let allowed = STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        let allowed = st.emitters.contains(&from);
+        if !allowed {
+            st.rejected_emits += 1;
+        }
+        allowed
+    });
+    if !allowed {
+        return Err(Error::Unauthorized("caller is not an emitter".into()));
+    }
+    event.emitter = from;
+    let (seq, max_events) = STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        st.next_seq += 1;
+        (st.next_seq - 1, st.cfg.max_events)
+    });
     EVENTS.with(|cell| {
-        let mut q = cell.borrow_mut();
-        if q.len() >= MAX_EVENTS {
-            q.pop_front();
+        let mut events = cell.borrow_mut();
+        let logged = LoggedEvent { seq, received_ns: now, event };
+        index(&logged);
+        events.insert(seq, logged);
+        trim(&mut events, max_events);
+    });
+    Ok(())
+}
+
+/// Drop the oldest events beyond `max_events`
+fn trim(events: &mut StableBTreeMap<u64, LoggedEvent, Memory>, max_events: u64) {
+    while events.len() > max_events {
+        if let Some((_, e)) = events.pop_first() {
+            unindex(&e);
+        }
+    }
+}
+
+fn kind_code(kind: EventKind) -> u8 {
+    EventKind::ALL.iter().position(|k| *k == kind).expect("kind listed in ALL") as u8
+}
+
+fn index(e: &LoggedEvent) {
+    BY_KIND.with(|m| m.borrow_mut().insert((kind_code(e.event.kind), e.seq), ()));
+    BY_ACTOR.with(|m| m.borrow_mut().insert((e.event.actor, e.seq), ()));
+    if let Some(subject) = e.event.subject {
+        BY_SUBJECT.with(|m| m.borrow_mut().insert((subject, e.seq), ()));
+    }
+}
+
+fn unindex(e: &LoggedEvent) {
+    BY_KIND.with(|m| m.borrow_mut().remove(&(kind_code(e.event.kind), e.seq)));
+    BY_ACTOR.with(|m| m.borrow_mut().remove(&(e.event.actor, e.seq)));
+    if let Some(subject) = e.event.subject {
+        BY_SUBJECT.with(|m| m.borrow_mut().remove(&(subject, e.seq)));
+    }
+}
+
+/// Return up to `limit` (at most `MAX_PAGE`) most recent events (newest first)
+#[query]
+fn list_recent(limit: u64) -> Vec<LoggedEvent> {
+    let limit = limit.min(MAX_PAGE) as usize;
+    EVENTS.with(|cell| cell.borrow().iter().rev().take(limit).map(|(_, e)| e).collect())
+}
+
+/// Up to `length` (at most `MAX_PAGE`) events from `start_seq` on, oldest first.
+/// Trimmed seqs are skipped, so a gap before the first result means events were lost.
+#[query]
+fn get_events(start_seq: u64, length: u64) -> Vec<LoggedEvent> {
+    let length = length.min(MAX_PAGE) as usize;
+    EVENTS.with(|cell| cell.borrow().range(start_seq..).take(length).map(|(_, e)| e).collect())
+}
+
+/// Up to `MAX_PAGE` events after `seq` (the last one the caller has seen)
+#[query]
+fn list_since(seq: u64) -> Vec<LoggedEvent> {
+    match seq.checked_add(1) {
+        Some(start) => get_events(start, MAX_PAGE),
+        None => Vec::new(),
+    }
+}
+
+/// Events matching `filter` from `start_seq` on, oldest first, at most `limit`
+/// (capped at `MAX_PAGE`). A page may come back short, even empty, with a
+/// `next_seq` when the scan budget ran out; keep paging until it is None.
+#[query]
+fn find_events(filter: EventFilter, start_seq: u64, limit: u64) -> EventPage {
+    let limit = limit.min(MAX_PAGE) as usize;
+    let (lo, hi) = seq_window(&filter, start_seq);
+    if lo >= hi || limit == 0 {
+        return EventPage { events: Vec::new(), next_seq: None };
+    }
+    let candidates = candidates(&filter, lo, hi);
+    let mut next_seq = match candidates.last() {
+        Some(last) if candidates.len() == MAX_SCAN => Some(last + 1),
+        _ => None,
+    };
+    let mut events = Vec::new();
+    EVENTS.with(|cell| {
+        let log = cell.borrow();
+        for seq in candidates {
+            if events.len() == limit {
+                next_seq = Some(seq);
+                break;
+            }
+            match log.get(&seq) {
+                Some(e) if matches(&filter, &e) => events.push(e),
+                _ => {}
+            }
         }
-        q.push_back(event);
     });
+    EventPage { events, next_seq }
+}
+
+/// Seqs `[lo, hi)` that can hold matches: from `start_seq` and within the
+/// time window. `received_ns` never decreases along the log, so the window
+/// bounds are found by binary search over seqs.
+fn seq_window(filter: &EventFilter, start_seq: u64) -> (u64, u64) {
+    let next_seq = STATE.with(|s| s.borrow().next_seq);
+    EVENTS.with(|cell| {
+        let log = cell.borrow();
+        let Some((first_seq, _)) = log.first_key_value() else {
+            return (0, 0);
+        };
+        // first seq received at or after `ns`
+        let seq_at = |ns: u64| {
+            let (mut lo, mut hi) = (first_seq, next_seq);
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+                if log.get(&mid).is_none_or(|e| e.received_ns < ns) {
+                    lo = mid + 1;
+                } else {
+                    hi = mid;
+                }
+            }
+            lo
+        };
+        let lo = start_seq.max(filter.from_ns.map_or(first_seq, seq_at));
+        let hi = filter.to_ns.map_or(next_seq, seq_at);
+        (lo, hi)
+    })
+}
+
+/// Up to `MAX_SCAN` seqs in `[lo, hi)`, ascending, from the narrowest index
+/// the filter allows; they still have to be checked with `matches`.
+fn candidates(filter: &EventFilter, lo: u64, hi: u64) -> Vec<u64> {
+    let by_principal = |m: &StableBTreeMap<(Principal, u64), (), Memory>, p: Principal| {
+        m.range((p, lo)..(p, hi)).take(MAX_SCAN).map(|((_, seq), _)| seq).collect()
+    };
+    if let Some(actor) = filter.actor {
+        return BY_ACTOR.with(|m| by_principal(&m.borrow(), actor));
+    }
+    if let Some(subject) = filter.subject {
+        return BY_SUBJECT.with(|m| by_principal(&m.borrow(), subject));
+    }
+    if let Some(prefix) = &filter.kind_prefix {
+        // the first MAX_SCAN of each matching kind cover the first MAX_SCAN overall
+        let mut seqs: Vec<u64> = BY_KIND.with(|m| {
+            let m = m.borrow();
+            EventKind::ALL
+                .iter()
+                .filter(|k| k.name().starts_with(prefix.as_str()))
+                .flat_map(|k| {
+                    let code = kind_code(*k);
+                    m.range((code, lo)..(code, hi)).take(MAX_SCAN).map(|((_, seq), _)| seq)
+                })
+                .collect()
+        });
+        seqs.sort_unstable();
+        seqs.truncate(MAX_SCAN);
+        return seqs;
+    }
+    EVENTS.with(|cell| cell.borrow().range(lo..hi).take(MAX_SCAN).map(|(seq, _)| seq).collect())
+}
+
+fn matches(filter: &EventFilter, e: &LoggedEvent) -> bool {
+    filter.kind_prefix.as_ref().is_none_or(|p| e.event.kind.name().starts_with(p.as_str()))
+        && filter.actor.is_none_or(|p| e.event.actor == p)
+        && filter.subject.is_none_or(|p| e.event.subject == Some(p))
+        && filter.from_ns.is_none_or(|t| e.received_ns >= t)
+        && filter.to_ns.is_none_or(|t| e.received_ns < t)
+}
+
+#[query]
+fn get_config() -> Config {
+    STATE.with(|s| s.borrow().cfg.clone())
 }
 
-/// Return up to `limit` most recent events (newest first)
 #[query]
-fn list_recent(limit: u64) -> Vec<String> {
+fn get_stats() -> Stats {
+    let (rejected_emits, next_seq) = STATE.with(|s| {
+        let st = s.borrow();
+        (st.rejected_emits, st.next_seq)
+    });
     EVENTS.with(|cell| {
-        let q = cell.borrow();
-        let take_n = (limit as usize).min(q.len());
-        q.iter().rev().take(take_n).cloned().collect()
+        let events = cell.borrow();
+        Stats {
+            events: events.len(),
+            rejected_emits,
+            first_seq: events.first_key_value().map(|(seq, _)| seq),
+            next_seq,
+        }
     })
 }
+
+#[query]
+fn list_emitters() -> Vec<Principal> {
+    let mut emitters: Vec<Principal> =
+        STATE.with(|s| s.borrow().emitters.iter().copied().collect());
+    emitters.sort();
+    emitters
+}
+
+#[update]
+fn add_emitter(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    STATE.with(|s| s.borrow_mut().emitters.insert(p));
+    Ok(())
+}
+
+#[update]
+fn remove_emitter(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    STATE.with(|s| s.borrow_mut().emitters.remove(&p));
+    Ok(())
+}
+
+/// Change retention; lowering it drops the oldest events right away
+#[update]
+fn set_max_events(max_events: u64) -> Result<(), Error> {
+    ensure_admin()?;
+    let mut cfg = STATE.with(|s| s.borrow().cfg.clone());
+    cfg.max_events = max_events;
+    validate_config(&cfg).map_err(Error::InvalidArgument)?;
+    STATE.with(|s| s.borrow_mut().cfg = cfg);
+    EVENTS.with(|cell| trim(&mut cell.borrow_mut(), max_events));
+    Ok(())
+}
+
+fn ensure_admin() -> Result<(), Error> {
+    if STATE.with(|s| s.borrow().admin) == caller() {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized("caller is not admin".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use icroots_events::{CollateralEvent, Payload, ReputeEvent};
+
+    const EMITTER: Principal = Principal::from_slice(&[2]);
+
+    fn event(level: u64) -> Event {
+        Event {
+            kind: EventKind::LevelChanged,
+            actor: Principal::anonymous(),
+            subject: Some(Principal::from_slice(&[1])),
+            payload: Payload::Repute(ReputeEvent::LevelChanged {
+                previous: 0,
+                level,
+                reason: None,
+            }),
+            timestamp_ns: level,
+            emitter: EMITTER,
+        }
+    }
+
+    /// `event(level)` as logged at seq `seq`, received at `level`
+    fn logged(seq: u64, level: u64) -> LoggedEvent {
+        LoggedEvent { seq, received_ns: level, event: event(level) }
+    }
+
+    fn allow_emitter() {
+        STATE.with(|s| s.borrow_mut().emitters.insert(EMITTER));
+    }
+
+    #[test]
+    fn retention_keeps_the_newest_events() {
+        STATE.with(|s| {
+            let mut st = s.borrow_mut();
+            st.cfg.max_events = 3;
+            st.emitters.insert(EMITTER);
+        });
+        for i in 0..5 {
+            record_emit(EMITTER, i, event(i)).unwrap();
+        }
+        assert_eq!(list_recent(10), [logged(4, 4), logged(3, 3), logged(2, 2)]);
+        assert_eq!(list_recent(1), [logged(4, 4)]);
+
+        // sequence numbers keep counting after trimming
+        EVENTS.with(|cell| trim(&mut cell.borrow_mut(), 1));
+        record_emit(EMITTER, 5, event(5)).unwrap();
+        assert_eq!(EVENTS.with(|cell| cell.borrow().first_key_value()), Some((4, logged(4, 4))));
+
+        pre_upgrade();
+        let saved = SAVED_STATE.with(|c| c.borrow().get().clone());
+        assert_eq!(saved.restore::<State>().unwrap().cfg.max_events, 3);
+    }
+
+    #[test]
+    fn only_allowed_emitters_are_logged_under_their_own_name() {
+        let loans = Principal::from_slice(&[3]);
+        STATE.with(|s| s.borrow_mut().emitters.insert(loans));
+
+        // a stranger is refused and counted, even if it claims to be loans
+        let spoofed = Event { emitter: loans, ..event(1) };
+        assert!(matches!(record_emit(EMITTER, 0, spoofed), Err(Error::Unauthorized(_))));
+        assert_eq!((get_stats().events, get_stats().rejected_emits), (0, 1));
+
+        // the bus records who actually called
+        record_emit(loans, 0, event(2)).unwrap();
+        assert_eq!(list_recent(1)[0].event.emitter, loans);
+        assert_eq!((get_stats().events, get_stats().rejected_emits), (1, 1));
+    }
+
+    #[test]
+    fn cursors_page_forward_without_gaps_or_duplicates() {
+        allow_emitter();
+        for i in 0..(MAX_PAGE + 20) {
+            record_emit(EMITTER, i, event(i)).unwrap();
+        }
+        assert_eq!(get_events(3, 2), [logged(3, 3), logged(4, 4)]);
+        assert_eq!(get_events(0, u64::MAX).len() as u64, MAX_PAGE);
+        assert_eq!(list_recent(u64::MAX).len() as u64, MAX_PAGE);
+        let newest = MAX_PAGE + 19;
+        assert_eq!(list_recent(2), [logged(newest, newest), logged(newest - 1, newest - 1)]);
+
+        // page with list_since from the last seq seen
+        let mut seen = get_events(0, 10);
+        while let Some(last) = seen.last().map(|e| e.seq) {
+            let page = list_since(last);
+            if page.is_empty() {
+                break;
+            }
+            seen.extend(page);
+        }
+        assert!(seen.iter().map(|e| e.seq).eq(0..MAX_PAGE + 20));
+        assert!(list_since(u64::MAX).is_empty());
+        let stats = get_stats();
+        assert_eq!((stats.first_seq, stats.next_seq), (Some(0), MAX_PAGE + 20));
+    }
+
+    /// Events about three borrowers (`seq % 3`), received at `seq * 10`:
+    /// seizes on even seqs, level changes on odd ones
+    fn emit_mixed(count: u64) {
+        allow_emitter();
+        for seq in 0..count {
+            let borrower = Principal::from_slice(&[10 + (seq % 3) as u8]);
+            let mut e = event(seq);
+            e.subject = Some(borrower);
+            if seq % 2 == 0 {
+                e.kind = EventKind::CollateralSeized;
+                e.payload = Payload::Collateral(CollateralEvent::Seized {
+                    loan_id: seq as u128,
+                    to: EMITTER,
+                    seized: 1,
+                    returned: 0,
+                });
+            }
+            record_emit(EMITTER, seq * 10, e).unwrap();
+        }
+    }
+
+    fn seqs(page: &EventPage) -> Vec<u64> {
+        page.events.iter().map(|e| e.seq).collect()
+    }
+
+    #[test]
+    fn find_events_filters_by_kind_subject_and_time() {
+        emit_mixed(12);
+        let borrower = Principal::from_slice(&[10]);
+
+        let seizes = EventFilter { kind_prefix: Some("collateral.".into()), ..Default::default() };
+        assert_eq!(seqs(&find_events(seizes.clone(), 0, 100)), [0, 2, 4, 6, 8, 10]);
+        let for_borrower = EventFilter { subject: Some(borrower), ..Default::default() };
+        assert_eq!(seqs(&find_events(for_borrower.clone(), 0, 100)), [0, 3, 6, 9]);
+
+        // combined, within [30, 90) ns
+        let filter = EventFilter { from_ns: Some(30), to_ns: Some(90), ..seizes };
+        assert_eq!(seqs(&find_events(filter.clone(), 0, 100)), [4, 6, 8]);
+        let filter = EventFilter { subject: Some(borrower), ..filter };
+        assert_eq!(seqs(&find_events(filter, 0, 100)), [6]);
+
+        // pages resume at next_seq
+        let page = find_events(for_borrower.clone(), 0, 3);
+        assert_eq!((seqs(&page), page.next_seq), (vec![0, 3, 6], Some(9)));
+        let page = find_events(for_borrower, 9, 3);
+        assert_eq!((seqs(&page), page.next_seq), (vec![9], None));
+        assert!(find_events(EventFilter::default(), 0, 0).events.is_empty());
+    }
+
+    #[test]
+    fn trimmed_events_leave_the_indexes() {
+        emit_mixed(12);
+        EVENTS.with(|cell| trim(&mut cell.borrow_mut(), 4));
+        let all = EventFilter::default();
+        assert_eq!(seqs(&find_events(all.clone(), 0, 100)), [8, 9, 10, 11]);
+        assert_eq!(BY_KIND.with(|m| m.borrow().len()), 4);
+        assert_eq!(BY_SUBJECT.with(|m| m.borrow().len()), 4);
+    }
+
+    #[test]
+    fn baseline_bus_upgrade_needs_the_peers_in_the_args() {
+        // what post_upgrade does when the baseline left stable memory empty
+        let admin = Principal::from_slice(&[0xad]);
+        let err = baseline_state(admin, &InitArgs::default()).err().unwrap();
+        assert!(err.contains("peer canisters"));
+
+        let loans = Principal::from_slice(&[0x10]);
+        let args = InitArgs { loans: Some(loans), ..InitArgs::default() };
+        let st = baseline_state(admin, &args).unwrap();
+        STATE.with(|s| *s.borrow_mut() = st);
+        allow_peers(&args);
+        assert_eq!(record_emit(loans, 1, event(1)), Ok(()));
+        assert_eq!(get_events(0, 10)[0].event.emitter, loans);
+        assert!(record_emit(EMITTER, 2, event(2)).is_err());
+
+        // later upgrades restore the saved allowlist
+        pre_upgrade();
+        let saved = SAVED_STATE.with(|c| c.borrow().get().clone());
+        let st = saved.restore::<State>().unwrap();
+        assert_eq!((st.admin, st.next_seq), (admin, 1));
+        assert!(st.emitters.contains(&loans));
+    }
+}
@@ -0,0 +1,83 @@
+//! Minimal ICRC-1 ledger types used to move the stablecoin (e.g. ckUSDC).
+
+use candid::{CandidType, Deserialize, Principal};
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+impl From<Principal> for Account {
+    fn from(owner: Principal) -> Self {
+        Self { owner, subaccount: None }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: u128,
+    pub fee: Option<u128>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferError {
+    BadFee { expected_fee: u128 },
+    BadBurn { min_burn_amount: u128 },
+    InsufficientFunds { balance: u128 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u128 },
+    TemporarilyUnavailable,
+    GenericError { error_code: u128, message: String },
+}
+
+/// `icrc1_transfer` from this canister's default account; returns the block index
+pub async fn transfer(ledger: Principal, arg: TransferArg) -> Result<u128, String> {
+    let res: Result<(Result<u128, TransferError>,), _> =
+        ic_cdk::call(ledger, "icrc1_transfer", (arg,)).await;
+    match res {
+        Ok((Ok(block),)) => Ok(block),
+        Ok((Err(e),)) => Err(format!("ledger rejected transfer: {e:?}")),
+        Err((code, msg)) => Err(format!("ledger call failed: {code:?} {msg}")),
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: u128,
+    pub fee: Option<u128>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferFromError {
+    BadFee { expected_fee: u128 },
+    BadBurn { min_burn_amount: u128 },
+    InsufficientFunds { balance: u128 },
+    InsufficientAllowance { allowance: u128 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u128 },
+    TemporarilyUnavailable,
+    GenericError { error_code: u128, message: String },
+}
+
+/// `icrc2_transfer_from` using an allowance granted to this canister; returns the block index
+pub async fn transfer_from(ledger: Principal, arg: TransferFromArgs) -> Result<u128, String> {
+    let res: Result<(Result<u128, TransferFromError>,), _> =
+        ic_cdk::call(ledger, "icrc2_transfer_from", (arg,)).await;
+    match res {
+        Ok((Ok(block),)) => Ok(block),
+        Ok((Err(e),)) => Err(format!("ledger rejected transfer_from: {e:?}")),
+        Err((code, msg)) => Err(format!("ledger call failed: {code:?} {msg}")),
+    }
+}
@@ -1,16 +1,168 @@
+use candid::utils::{ArgumentDecoder, ArgumentEncoder};
 use candid::{CandidType, Deserialize, Principal};
 use ic_cdk::{
-    api::{caller, time},
+    api::{call::RejectionCode, caller, time},
     call, trap,
 };
-use ic_cdk::storage::{stable_restore, stable_save};
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
-use serde_json::json;
+use ic_cdk_timers::{set_timer, set_timer_interval};
+use icroots_events::{Event, EventKind, LoansEvent, Payload};
+use icroots_state::{decode, encode, Versioned};
+use icroots_valuation::{CollateralNeeds, LoanDebt, PriceStatus, Valuation, BPS_DENOM};
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::Duration;
+
+mod icrc;
+mod store;
+
+/// Persisted layout (see `icroots_state`).
+/// 0: the first deployed `State` (interest-free loans, no config);
+/// 1: loans, applications, users and credits in stable maps (see `store`)
+const STATE_VERSION: u32 = 1;
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const NANOS_PER_YEAR: u128 = 365 * NANOS_PER_DAY as u128;
+/// Longest idempotency key accepted by `request_loan`/`repay`
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 64;
+/// Upper bound on `Config::dependency_retries`, to keep a call's cycle cost bounded
+const MAX_DEPENDENCY_RETRIES: u32 = 5;
+/// How often the timer re-checks open loans for overdue/default transitions
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Open loans refreshed per sweep message; longer sweeps continue in a new one
+const SWEEP_BATCH: usize = 500;
+
+/// Lending parameters (tunable at deploy/init time)
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Config {
+    /// Annual interest rate (basis points) fixed on each loan at origination
+    apr_bps: u32,
+    /// Term used when the borrower does not request one
+    default_term_days: u32,
+    /// Longest term a borrower may request
+    max_term_days: u32,
+    /// Days after the due date before an unpaid loan is marked Overdue
+    overdue_grace_days: u32,
+    /// Days after the due date before an unpaid loan is marked Defaulted
+    default_grace_days: u32,
+    /// Max loan-to-value by reputation level, ascending by `min_level`
+    ltv_tiers: Vec<LtvTier>,
+    /// Loans become liquidatable once debt exceeds this share of locked collateral value
+    liquidation_threshold_bps: u32,
+    /// Extra collateral seized on liquidation, as basis points of the debt
+    liquidation_penalty_bps: u32,
+    /// Reputation gained for a loan repaid in full without any late payment
+    rep_on_time_step: u64,
+    /// Reputation lost for each payment made while the loan is Overdue
+    rep_late_step: u64,
+    /// Reputation lost when a loan defaults
+    rep_default_step: u64,
+    /// Repayments never raise a level above this
+    rep_level_cap: u64,
+    /// Late payments and defaults never lower a level below this
+    rep_level_floor: u64,
+    /// Extra attempts for dependency reads rejected with a transient error
+    dependency_retries: u32,
+    /// How long an idempotency key is remembered (ICRC-1 `TX_WINDOW`)
+    dedup_window_secs: u64,
+    /// Clock skew tolerated on `created_at_time` (ICRC-1 `PERMITTED_DRIFT`)
+    dedup_drift_secs: u64,
+    /// What `repay` does with the part of a payment above what is still due
+    overpayment_policy: OverpaymentPolicy,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+enum OverpaymentPolicy {
+    /// Fail the repayment; nothing is collected
+    Reject,
+    /// Collect only what is due and report the difference
+    Cap,
+    /// Collect everything and credit the excess to the borrower, to be
+    /// spent on later repayments
+    Credit,
+}
+
+impl fmt::Display for OverpaymentPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct LtvTier {
+    /// Lowest reputation level this tier applies to
+    min_level: u64,
+    /// Max (outstanding + requested) as basis points of collateral value
+    max_ltv_bps: u32,
+}
+
+impl Config {
+    fn max_ltv_bps(&self, level: u64) -> u32 {
+        self.ltv_tiers
+            .iter()
+            .take_while(|t| t.min_level <= level)
+            .last()
+            .map_or(0, |t| t.max_ltv_bps)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            apr_bps: 1_000, // 10% APR demo default
+            default_term_days: 30,
+            max_term_days: 365,
+            overdue_grace_days: 3,
+            default_grace_days: 30,
+            ltv_tiers: vec![
+                LtvTier { min_level: 0, max_ltv_bps: 3_000 },
+                LtvTier { min_level: 50, max_ltv_bps: 5_000 },
+                LtvTier { min_level: 80, max_ltv_bps: 6_500 },
+            ],
+            liquidation_threshold_bps: 8_000,
+            liquidation_penalty_bps: 1_000,
+            rep_on_time_step: 5,
+            rep_late_step: 3,
+            rep_default_step: 20,
+            rep_level_cap: 100,
+            rep_level_floor: 0,
+            dependency_retries: 2,
+            dedup_window_secs: 24 * 60 * 60,
+            dedup_drift_secs: 60,
+            overpayment_policy: OverpaymentPolicy::Cap,
+        }
+    }
+}
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 struct State {
+    admin: Principal,
+    repute: Principal,
+    collateral: Principal,
+    trust_ai: Principal,
+    event_bus: Option<Principal>,
+    /// BTC/USD price feed; without one collateral is valued 1:1 (mock units)
+    oracle: Option<Principal>,
+    /// ICRC-1 stablecoin ledger used to disburse loans; None = no funds move (demo)
+    ledger: Option<Principal>,
+    /// Subaccount of this canister that receives repayments
+    treasury_subaccount: Option<Vec<u8>>,
+    next_loan_id: u128,
+    /// Principals (besides admin) allowed to call `liquidate`
+    liquidators: HashSet<Principal>,
+    /// Loan ids whose collateral release failed and is retried by the sweep
+    pending_releases: HashSet<u128>,
+    /// Principals (besides admin) allowed to decide REVIEW applications
+    underwriters: HashSet<Principal>,
+    next_application_id: u128,
+    /// Results of keyed `request_loan`/`repay` calls, by (caller, key)
+    dedup: HashMap<(Principal, Vec<u8>), DedupEntry>,
+    cfg: Config,
+}
+
+/// Version 0 `State`, as `stable_save`d by the first deployed build
+#[derive(CandidType, Deserialize)]
+struct StateV0 {
     admin: Principal,
     repute: Principal,
     collateral: Principal,
@@ -18,7 +170,72 @@ struct State {
     event_bus: Option<Principal>,
     next_loan_id: u128,
     users: HashSet<Principal>,
-    loans: HashMap<u128, Loan>,
+    loans: HashMap<u128, LoanV0>,
+}
+
+/// Version 0 `Loan`: no interest, term or collateral
+#[derive(CandidType, Deserialize)]
+struct LoanV0 {
+    id: u128,
+    borrower: Principal,
+    amount: u128,
+    repaid: u128,
+    status: LoanStatus,
+    created_at_ns: u64,
+}
+
+/// v0 → v1: users and loans move to stable maps; everything added since
+/// starts empty or at its default
+fn migrate_v0(old: StateV0) -> State {
+    let cfg = Config::default();
+    old.users.into_iter().for_each(store::register);
+    for l in old.loans.into_values() {
+        store::put_loan(migrate_v0_loan(l, &cfg));
+    }
+    State {
+        admin: old.admin,
+        repute: old.repute,
+        collateral: old.collateral,
+        trust_ai: old.trust_ai,
+        event_bus: old.event_bus,
+        next_loan_id: old.next_loan_id,
+        cfg,
+        ..State::default()
+    }
+}
+
+/// A v0 loan becomes an interest-free, single-installment loan over the
+/// default term, with what was already repaid applied when it was opened
+fn migrate_v0_loan(old: LoanV0, cfg: &Config) -> Loan {
+    let term_ns = cfg.default_term_days as u64 * NANOS_PER_DAY;
+    let start = old.created_at_ns;
+    let mut loan = Loan {
+        id: old.id,
+        borrower: old.borrower,
+        amount: old.amount,
+        repaid: 0,
+        status: LoanStatus::Active,
+        created_at_ns: start,
+        apr_bps: 0,
+        principal_repaid: 0,
+        interest_paid: 0,
+        interest_accrued: 0,
+        accrued_at_ns: start,
+        due_at_ns: start.saturating_add(term_ns),
+        schedule: build_schedule(old.amount, 0, start, term_ns, &RepaymentPlan::default()),
+        collateral_locked: 0,
+        liquidation: None,
+        disbursement_block: None,
+        repayments: Vec::new(),
+        paid_late: false,
+    };
+    if old.repaid > 0 {
+        loan.apply_payment(old.repaid, start);
+    }
+    if old.status == LoanStatus::Repaid {
+        loan.status = LoanStatus::Repaid;
+    }
+    loan
 }
 
 impl Default for State {
@@ -29,9 +246,16 @@ impl Default for State {
             collateral: Principal::anonymous(),
             trust_ai: Principal::anonymous(),
             event_bus: None,
+            oracle: None,
+            ledger: None,
+            treasury_subaccount: None,
             next_loan_id: 1,
-            users: HashSet::new(),
-            loans: HashMap::new(),
+            liquidators: HashSet::new(),
+            pending_releases: HashSet::new(),
+            underwriters: HashSet::new(),
+            next_application_id: 1,
+            dedup: HashMap::new(),
+            cfg: Config::default(),
         }
     }
 }
@@ -44,12 +268,316 @@ struct Loan {
     repaid: u128,
     status: LoanStatus,
     created_at_ns: u64,
+    /// Annual interest rate (basis points) set at origination
+    apr_bps: u32,
+    /// Part of `repaid` that settled principal
+    principal_repaid: u128,
+    /// Part of `repaid` that settled interest
+    interest_paid: u128,
+    /// Interest accrued but not yet paid, as of `accrued_at_ns`
+    interest_accrued: u128,
+    accrued_at_ns: u64,
+    /// End of the term (due date of the last installment)
+    due_at_ns: u64,
+    /// Installments, oldest first; payments settle them in order
+    schedule: Vec<Installment>,
+    /// Collateral held by `collateral_backend` for this loan; zeroed once released
+    collateral_locked: u128,
+    liquidation: Option<Liquidation>,
+    /// Ledger block of the disbursement transfer (None without a ledger)
+    disbursement_block: Option<u128>,
+    repayments: Vec<Repayment>,
+    /// Set once any payment lands while the loan is Overdue or Defaulted
+    paid_late: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Repayment {
+    amount: u128,
+    at_ns: u64,
+    /// Ledger block of the `icrc2_transfer_from` (None without a ledger)
+    block_index: Option<u128>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Liquidation {
+    at_ns: u64,
+    liquidator: Principal,
+    /// Receives the seized collateral (liquidator, or this canister for the protocol)
+    recipient: Principal,
+    /// Principal + interest owed when liquidated
+    debt: u128,
+    /// Collateral units moved to `recipient`
+    seized: u128,
+    /// Collateral units returned to the borrower
+    returned: u128,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+enum Amortization {
+    /// Same principal every installment; interest shrinks with the balance
+    EqualPrincipal,
+    /// Same total payment every installment (principal + interest)
+    Annuity,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct RepaymentPlan {
+    style: Amortization,
+    installments: u32,
+}
+
+impl Default for RepaymentPlan {
+    /// A single bullet payment at the end of the term
+    fn default() -> Self {
+        Self { style: Amortization::EqualPrincipal, installments: 1 }
+    }
+}
+
+/// One scheduled repayment. `interest` is projected at origination assuming
+/// on-time payments; actual interest keeps accruing on the loan itself.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Installment {
+    index: u32,
+    due_at_ns: u64,
+    principal: u128,
+    interest: u128,
+    principal_paid: u128,
+    interest_paid: u128,
+    paid_at_ns: Option<u64>,
+}
+
+/// Split `amount` into installments over `term_ns` starting at `start_ns`
+fn build_schedule(
+    amount: u128,
+    apr_bps: u32,
+    start_ns: u64,
+    term_ns: u64,
+    plan: &RepaymentPlan,
+) -> Vec<Installment> {
+    let n = plan.installments.max(1);
+    let period_ns = term_ns / n as u64;
+    let period_interest = |balance: u128| {
+        balance.saturating_mul(apr_bps as u128).saturating_mul(period_ns as u128)
+            / (BPS_DENOM * NANOS_PER_YEAR)
+    };
+
+    // level payment for annuities: P·r / (1 − (1 + r)^−n)
+    let rate = apr_bps as f64 / BPS_DENOM as f64 * period_ns as f64 / NANOS_PER_YEAR as f64;
+    let annuity_payment = if rate == 0.0 {
+        amount / n as u128
+    } else {
+        (amount as f64 * rate / (1.0 - (1.0 + rate).powi(-(n as i32)))).round() as u128
+    };
+
+    let mut balance = amount;
+    (0..n)
+        .map(|i| {
+            let last = i + 1 == n;
+            let interest = period_interest(balance);
+            let principal = if last {
+                balance
+            } else {
+                match plan.style {
+                    Amortization::EqualPrincipal => amount / n as u128,
+                    Amortization::Annuity => annuity_payment.saturating_sub(interest),
+                }
+                .min(balance)
+            };
+            balance -= principal;
+            Installment {
+                index: i,
+                due_at_ns: if last {
+                    start_ns.saturating_add(term_ns)
+                } else {
+                    start_ns.saturating_add(period_ns * (i as u64 + 1))
+                },
+                principal,
+                interest,
+                principal_paid: 0,
+                interest_paid: 0,
+                paid_at_ns: None,
+            }
+        })
+        .collect()
+}
+
+impl Loan {
+    fn principal_outstanding(&self) -> u128 {
+        self.amount.saturating_sub(self.principal_repaid)
+    }
+
+    /// Unpaid interest as of `now` (simple interest on outstanding principal)
+    fn interest_at(&self, now: u64) -> u128 {
+        let elapsed = now.saturating_sub(self.accrued_at_ns) as u128;
+        let fresh = self
+            .principal_outstanding()
+            .saturating_mul(self.apr_bps as u128)
+            .saturating_mul(elapsed)
+            / (BPS_DENOM * NANOS_PER_YEAR);
+        self.interest_accrued.saturating_add(fresh)
+    }
+
+    fn total_due_at(&self, now: u64) -> u128 {
+        self.principal_outstanding().saturating_add(self.interest_at(now))
+    }
+
+    /// Roll accrued interest forward to `now`
+    fn accrue(&mut self, now: u64) {
+        self.interest_accrued = self.interest_at(now);
+        self.accrued_at_ns = now;
+    }
+
+    /// Due date of the oldest unpaid installment
+    fn next_due_at(&self) -> u64 {
+        self.schedule
+            .iter()
+            .find(|i| i.paid_at_ns.is_none())
+            .map_or(self.due_at_ns, |i| i.due_at_ns)
+    }
+
+    fn days_past_due(&self, now: u64) -> u64 {
+        if self.status.is_open() {
+            now.saturating_sub(self.next_due_at()) / NANOS_PER_DAY
+        } else {
+            0
+        }
+    }
+
+    /// Advance Active → Overdue → Defaulted once the grace periods on the
+    /// oldest unpaid installment lapse; an Overdue loan that caught up goes
+    /// back to Active. Returns the new status when it changed.
+    fn refresh_status(&mut self, now: u64, cfg: &Config) -> Option<LoanStatus> {
+        let due_at = self.next_due_at();
+        let overdue_at = due_at.saturating_add(cfg.overdue_grace_days as u64 * NANOS_PER_DAY);
+        let default_at = due_at.saturating_add(cfg.default_grace_days as u64 * NANOS_PER_DAY);
+
+        let next = match self.status {
+            LoanStatus::Active | LoanStatus::Overdue if now > default_at => LoanStatus::Defaulted,
+            LoanStatus::Active if now > overdue_at => LoanStatus::Overdue,
+            LoanStatus::Overdue if now <= overdue_at => LoanStatus::Active,
+            _ => return None,
+        };
+        self.status = next.clone();
+        Some(next)
+    }
+
+    /// Apply a payment at `now`: accrued interest is settled before principal,
+    /// and principal goes to the oldest unpaid installment first.
+    fn apply_payment(&mut self, amount: u128, now: u64) {
+        self.accrue(now);
+        let to_interest = amount.min(self.interest_accrued);
+        let to_principal = (amount - to_interest).min(self.principal_outstanding());
+
+        self.interest_accrued -= to_interest;
+        self.interest_paid = self.interest_paid.saturating_add(to_interest);
+        self.principal_repaid = self.principal_repaid.saturating_add(to_principal);
+        self.repaid = self.repaid.saturating_add(amount);
+
+        let mut open = self.schedule.iter_mut().filter(|i| i.paid_at_ns.is_none()).peekable();
+        if let Some(oldest) = open.peek_mut() {
+            oldest.interest_paid = oldest.interest_paid.saturating_add(to_interest);
+        }
+        let mut left = to_principal;
+        for inst in open {
+            let take = left.min(inst.principal - inst.principal_paid);
+            inst.principal_paid += take;
+            left -= take;
+            if inst.principal_paid < inst.principal {
+                break;
+            }
+            inst.paid_at_ns = Some(now);
+        }
+
+        if self.principal_outstanding() == 0 && self.interest_accrued == 0 {
+            self.status = LoanStatus::Repaid;
+        }
+    }
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
 enum LoanStatus {
     Active,
+    /// Past due plus the overdue grace period
+    Overdue,
+    /// Past due plus the default grace period
+    Defaulted,
     Repaid,
+    /// Closed by seizing the locked collateral
+    Liquidated,
+}
+
+impl LoanStatus {
+    /// Loans that still owe principal or interest
+    fn is_open(&self) -> bool {
+        matches!(self, LoanStatus::Active | LoanStatus::Overdue | LoanStatus::Defaulted)
+    }
+}
+
+/// Failure reasons returned by updates (and queries that look up a record)
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+enum Error {
+    NotRegistered,
+    LoanNotFound,
+    ApplicationNotFound,
+    /// Another `request_loan`/`repay` from the same borrower hasn't finished
+    CallInProgress,
+    /// `created_at_time` is older than the dedup window
+    TooOld,
+    CreatedInFuture { canister_time: u64 },
+    Unauthorized(String),
+    InvalidAmount(String),
+    InvalidArgument(String),
+    /// The loan or application is not in a state that allows the call
+    InvalidState(String),
+    InsufficientCollateral { free: u128, required: u128 },
+    /// No fresh collateral price to value collateral with
+    PriceUnavailable(String),
+    DependencyUnavailable(String),
+    TransferFailed(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotRegistered => write!(f, "not registered"),
+            Error::LoanNotFound => write!(f, "loan not found"),
+            Error::ApplicationNotFound => write!(f, "application not found"),
+            Error::CallInProgress => write!(f, "another call from this borrower is in progress"),
+            Error::TooOld => write!(f, "created_at_time is outside the dedup window"),
+            Error::CreatedInFuture { canister_time } => {
+                write!(f, "created_at_time is ahead of canister time {canister_time}")
+            }
+            Error::Unauthorized(msg) => write!(f, "unauthorized: {msg}"),
+            Error::InsufficientCollateral { free, required } => {
+                write!(f, "free collateral {free} < required {required}")
+            }
+            Error::InvalidAmount(msg)
+            | Error::InvalidArgument(msg)
+            | Error::InvalidState(msg)
+            | Error::PriceUnavailable(msg)
+            | Error::DependencyUnavailable(msg)
+            | Error::TransferFailed(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+/// `collateral_backend`'s error variant, decoded from its replies
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum CollateralError {
+    Unauthorized(String),
+    InvalidAmount(String),
+    InsufficientCollateral { free: u128, locked: u128 },
+    AlreadyLocked,
+    LockNotFound,
+    DependencyUnavailable(String),
+}
+
+/// `repute_backend`'s error variant, decoded from its replies
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum ReputeError {
+    Unauthorized(String),
+    InvalidArgument(String),
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug, Default)]
@@ -59,10 +587,60 @@ struct InitArgs {
     collateral: Option<Principal>,
     trust_ai: Option<Principal>,
     event_bus: Option<Principal>,
+    oracle: Option<Principal>,
+    ledger: Option<Principal>,
+    treasury_subaccount: Option<Vec<u8>>,
+    /// Optional APR override (basis points) for new loans
+    apr_bps: Option<u32>,
+    default_term_days: Option<u32>,
+    max_term_days: Option<u32>,
+    overdue_grace_days: Option<u32>,
+    default_grace_days: Option<u32>,
+    ltv_tiers: Option<Vec<LtvTier>>,
+    liquidation_threshold_bps: Option<u32>,
+    liquidation_penalty_bps: Option<u32>,
+    rep_on_time_step: Option<u64>,
+    rep_late_step: Option<u64>,
+    rep_default_step: Option<u64>,
+    rep_level_cap: Option<u64>,
+    rep_level_floor: Option<u64>,
+    dependency_retries: Option<u32>,
+    dedup_window_secs: Option<u64>,
+    dedup_drift_secs: Option<u64>,
+    overpayment_policy: Option<OverpaymentPolicy>,
+    liquidators: Option<Vec<Principal>>,
+    underwriters: Option<Vec<Principal>>,
 }
 
 thread_local! {
     static STATE: RefCell<State> = RefCell::new(State::default());
+    /// Borrowers with a `request_loan`/`repay` in progress (not persisted)
+    static IN_FLIGHT: RefCell<HashSet<Principal>> = RefCell::new(HashSet::new());
+}
+
+/// Marks a borrower busy while one of their calls awaits other canisters, so
+/// overlapping calls can't all pass the same checks. Dropping it frees the
+/// borrower again; ic-cdk also drops it when the call traps after an await.
+struct CallerGuard {
+    principal: Principal,
+}
+
+impl CallerGuard {
+    fn acquire(principal: Principal) -> Result<Self, Error> {
+        IN_FLIGHT.with(|f| {
+            if f.borrow_mut().insert(principal) {
+                Ok(Self { principal })
+            } else {
+                Err(Error::CallInProgress)
+            }
+        })
+    }
+}
+
+impl Drop for CallerGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT.with(|f| f.borrow_mut().remove(&self.principal));
+    }
 }
 
 #[init]
@@ -76,20 +654,257 @@ fn init(args: Option<InitArgs>) {
         st.collateral = args.collateral.unwrap_or(Principal::anonymous());
         st.trust_ai = args.trust_ai.unwrap_or(Principal::anonymous());
         st.event_bus = args.event_bus;
+        st.oracle = args.oracle;
+        st.ledger = args.ledger;
+        if let Some(sub) = args.treasury_subaccount {
+            if sub.len() != 32 {
+                trap("treasury_subaccount must be 32 bytes");
+            }
+            st.treasury_subaccount = Some(sub);
+        }
+        if let Some(v) = args.apr_bps { st.cfg.apr_bps = v; }
+        if let Some(v) = args.default_term_days { st.cfg.default_term_days = v; }
+        if let Some(v) = args.max_term_days { st.cfg.max_term_days = v; }
+        if let Some(v) = args.overdue_grace_days { st.cfg.overdue_grace_days = v; }
+        if let Some(v) = args.default_grace_days { st.cfg.default_grace_days = v; }
+        if let Some(v) = args.ltv_tiers { st.cfg.ltv_tiers = v; }
+        if let Some(v) = args.liquidation_threshold_bps { st.cfg.liquidation_threshold_bps = v; }
+        if let Some(v) = args.liquidation_penalty_bps { st.cfg.liquidation_penalty_bps = v; }
+        if let Some(v) = args.rep_on_time_step { st.cfg.rep_on_time_step = v; }
+        if let Some(v) = args.rep_late_step { st.cfg.rep_late_step = v; }
+        if let Some(v) = args.rep_default_step { st.cfg.rep_default_step = v; }
+        if let Some(v) = args.rep_level_cap { st.cfg.rep_level_cap = v; }
+        if let Some(v) = args.rep_level_floor { st.cfg.rep_level_floor = v; }
+        if let Some(v) = args.dependency_retries { st.cfg.dependency_retries = v; }
+        if let Some(v) = args.dedup_window_secs { st.cfg.dedup_window_secs = v; }
+        if let Some(v) = args.dedup_drift_secs { st.cfg.dedup_drift_secs = v; }
+        if let Some(v) = args.overpayment_policy { st.cfg.overpayment_policy = v; }
+        st.liquidators.extend(args.liquidators.unwrap_or_default());
+        st.underwriters.extend(args.underwriters.unwrap_or_default());
+        validate_config(&st.cfg).unwrap_or_else(|e| trap(&e));
     });
+    start_sweep_timer();
 }
 
+fn validate_config(cfg: &Config) -> Result<(), String> {
+    if cfg.default_term_days == 0 || cfg.default_term_days > cfg.max_term_days {
+        return Err("default_term_days must be in 1..=max_term_days".into());
+    }
+    if cfg.default_grace_days < cfg.overdue_grace_days {
+        return Err("default_grace_days must be >= overdue_grace_days".into());
+    }
+    if cfg.ltv_tiers.first().map(|t| t.min_level) != Some(0) {
+        return Err("ltv_tiers must start at min_level 0".into());
+    }
+    if cfg.ltv_tiers.windows(2).any(|w| w[0].min_level >= w[1].min_level) {
+        return Err("ltv_tiers must be strictly ascending by min_level".into());
+    }
+    if cfg.ltv_tiers.iter().any(|t| t.max_ltv_bps == 0 || t.max_ltv_bps as u128 > BPS_DENOM) {
+        return Err("max_ltv_bps must be in 1..=10000".into());
+    }
+    if cfg.liquidation_threshold_bps == 0 || cfg.liquidation_threshold_bps as u128 > BPS_DENOM {
+        return Err("liquidation_threshold_bps must be in 1..=10000".into());
+    }
+    if cfg.rep_level_floor > cfg.rep_level_cap {
+        return Err("rep_level_floor must be <= rep_level_cap".into());
+    }
+    if [cfg.rep_on_time_step, cfg.rep_late_step, cfg.rep_default_step]
+        .iter()
+        .any(|&step| step > i64::MAX as u64)
+    {
+        return Err("reputation steps must fit in int64".into());
+    }
+    if cfg.dedup_window_secs == 0 {
+        return Err("dedup_window_secs must be > 0".into());
+    }
+    if cfg.dependency_retries > MAX_DEPENDENCY_RETRIES {
+        return Err(format!("dependency_retries must be <= {MAX_DEPENDENCY_RETRIES}"));
+    }
+    Ok(())
+}
+
+fn start_sweep_timer() {
+    set_timer_interval(SWEEP_INTERVAL, || ic_cdk::spawn(sweep_overdue()));
+}
+
+/// Maps live in stable memory already; only the small `State` is saved
 #[pre_upgrade]
 fn pre_upgrade() {
-    let st = STATE.with(|s| s.borrow().clone());
-    stable_save((st,)).expect("stable_save failed");
+    STATE.with(|s| store::save_state(&s.borrow()));
 }
 
 #[post_upgrade]
 fn post_upgrade() {
-    let (st,): (State,) =
-        stable_restore().unwrap_or((State { admin: caller(), ..State::default() },));
+    let st = icroots_state::restore_or_trap(store::saved_state);
     STATE.with(|s| *s.borrow_mut() = st);
+    start_sweep_timer();
+}
+
+impl Versioned for State {
+    const VERSION: u32 = STATE_VERSION;
+
+    fn migrate_step(from: u32, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        match from {
+            0 => encode(&migrate_v0(decode(bytes)?)),
+            _ => Err(format!("no migration from state version {from}")),
+        }
+    }
+}
+
+/// Status change produced by `refresh_status`, kept for event emission
+struct Transition {
+    loan_id: u128,
+    borrower: Principal,
+    status: LoanStatus,
+    days_past_due: u64,
+}
+
+/// Re-evaluate the given loans in place and store any status changes
+fn refresh_statuses(now: u64, loans: &mut [Loan]) -> Vec<Transition> {
+    let cfg = STATE.with(|s| s.borrow().cfg.clone());
+    loans
+        .iter_mut()
+        .filter(|l| l.status.is_open())
+        .filter_map(|l| {
+            let status = l.refresh_status(now, &cfg)?;
+            let t = Transition {
+                loan_id: l.id,
+                borrower: l.borrower,
+                status,
+                days_past_due: l.days_past_due(now),
+            };
+            store::put_loan(l.clone());
+            Some(t)
+        })
+        .collect()
+}
+
+/// best-effort audit events for overdue/default transitions; defaults also
+/// cost the borrower reputation
+async fn emit_transitions(transitions: Vec<Transition>) {
+    for t in transitions.iter().filter(|t| t.status == LoanStatus::Defaulted) {
+        adjust_reputation(t.borrower, t.loan_id, ReputationEvent::Defaulted).await;
+    }
+    for t in transitions {
+        let kind = match t.status {
+            LoanStatus::Overdue => EventKind::LoanOverdue,
+            LoanStatus::Defaulted => EventKind::LoanDefaulted,
+            _ => continue,
+        };
+        let details = LoansEvent::StatusChanged {
+            loan_id: t.loan_id,
+            days_past_due: t.days_past_due,
+        };
+        emit_event(kind, ic_cdk::id(), Some(t.borrower), details).await;
+    }
+}
+
+// best-effort audit event
+async fn emit_event(
+    kind: EventKind,
+    actor: Principal,
+    subject: Option<Principal>,
+    details: LoansEvent,
+) {
+    if let Some(bus) = STATE.with(|s| s.borrow().event_bus) {
+        let event = Event::new(kind, actor, subject, Payload::Loans(details));
+        icroots_events::emit(bus, event).await;
+    }
+}
+
+/// Repayment behaviour that moves the borrower's reputation level
+#[derive(Debug, PartialEq, Eq)]
+enum ReputationEvent {
+    RepaidOnTime,
+    LatePayment { days_past_due: u64 },
+    Defaulted,
+}
+
+impl ReputationEvent {
+    fn delta(&self, cfg: &Config) -> i64 {
+        match self {
+            ReputationEvent::RepaidOnTime => cfg.rep_on_time_step as i64,
+            ReputationEvent::LatePayment { .. } => -(cfg.rep_late_step as i64),
+            ReputationEvent::Defaulted => -(cfg.rep_default_step as i64),
+        }
+    }
+
+    fn reason(&self, loan_id: u128) -> String {
+        match self {
+            ReputationEvent::RepaidOnTime => format!("loan {loan_id} repaid in full on time"),
+            ReputationEvent::LatePayment { days_past_due } => {
+                format!("late payment on loan {loan_id} ({days_past_due} days past due)")
+            }
+            ReputationEvent::Defaulted => format!("loan {loan_id} defaulted"),
+        }
+    }
+}
+
+/// Move the borrower's level in `repute_backend` within the configured
+/// floor/cap (best-effort, like event emission)
+async fn adjust_reputation(borrower: Principal, loan_id: u128, event: ReputationEvent) {
+    let (repute, cfg) = STATE.with(|s| {
+        let st = s.borrow();
+        (st.repute, st.cfg.clone())
+    });
+    let delta = event.delta(&cfg);
+    if delta == 0 {
+        return;
+    }
+    let args = (borrower, delta, cfg.rep_level_floor, cfg.rep_level_cap, event.reason(loan_id));
+    let _: Result<(Result<u64, ReputeError>,), _> = call(repute, "adjust_level", args).await;
+}
+
+async fn sweep_overdue() {
+    prune_dedup(time());
+    sweep_open_loans(None).await;
+    retry_pending_releases().await;
+}
+
+/// Refresh one page of open loans; a full page schedules the next one in a
+/// fresh message so large books don't hit the instruction limit
+async fn sweep_open_loans(cursor: Option<(Principal, u128)>) {
+    let (transitions, next) = sweep_page(time(), cursor);
+    if let Some(next) = next {
+        set_timer(Duration::ZERO, move || ic_cdk::spawn(sweep_open_loans(Some(next))));
+    }
+    emit_transitions(transitions).await;
+}
+
+/// Refresh up to `SWEEP_BATCH` open loans after `cursor`; also returns where
+/// the next page starts, if there may be one
+fn sweep_page(
+    now: u64,
+    cursor: Option<(Principal, u128)>,
+) -> (Vec<Transition>, Option<(Principal, u128)>) {
+    let mut page = store::open_loans_after(cursor, SWEEP_BATCH);
+    let next = match page.last() {
+        Some(l) if page.len() == SWEEP_BATCH => Some((l.borrower, l.id)),
+        _ => None,
+    };
+    (refresh_statuses(now, &mut page), next)
+}
+
+/// Release the collateral lock of a closed (or rolled back) loan; on failure
+/// the id is queued in `pending_releases` so the sweep can retry later.
+async fn release_collateral(loan_id: u128) {
+    let col_id = STATE.with(|s| s.borrow().collateral);
+    let res: Result<(Result<u128, CollateralError>,), _> =
+        call(col_id, "release", (loan_id,)).await;
+    if !matches!(res, Ok((Ok(_),))) {
+        STATE.with(|s| s.borrow_mut().pending_releases.insert(loan_id));
+        return;
+    }
+    STATE.with(|s| s.borrow_mut().pending_releases.remove(&loan_id));
+    store::update_loan(loan_id, |l| l.collateral_locked = 0);
+}
+
+async fn retry_pending_releases() {
+    let pending: Vec<u128> =
+        STATE.with(|s| s.borrow().pending_releases.iter().copied().collect());
+    for loan_id in pending {
+        release_collateral(loan_id).await;
+    }
 }
 
 #[query]
@@ -99,10 +914,7 @@ fn ping() -> String {
 
 #[update]
 fn register_user() {
-    let me = caller();
-    STATE.with(|s| {
-        s.borrow_mut().users.insert(me);
-    });
+    store::register(caller());
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -110,64 +922,172 @@ struct LoanInfo {
     id: u128,
     amount: u128,
     status: String,
+    apr_bps: u32,
+    due_at_ns: u64,
+    /// Due date of the oldest unpaid installment
+    next_due_at_ns: u64,
+    days_past_due: u64,
+    principal_outstanding: u128,
+    interest_accrued: u128,
+    total_due: u128,
+    collateral_locked: u128,
+    /// 10_000 = at the liquidation threshold; None when closed, unpriced or
+    /// without locked collateral
+    health_factor_bps: Option<u128>,
+    disbursement_block: Option<u128>,
+}
+
+/// Summary fields fed by other canisters
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+enum SummaryField {
+    Level,
+    Collateral,
+    CollateralValue,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 struct Summary {
     registered: bool,
-    level: u64,
-    collateral: u128,
+    /// None when `repute_backend` could not be reached
+    level: Option<u64>,
+    /// None when `collateral_backend` could not be reached
+    collateral: Option<u128>,
+    /// `collateral` in loan units; None without collateral or a price
+    collateral_value: Option<u128>,
+    /// Fields computed from out-of-date inputs (e.g. a stale oracle price)
+    stale: Vec<SummaryField>,
+    /// Fields left empty because a dependency was unavailable
+    missing: Vec<SummaryField>,
+    /// Total due across active loans (principal + accrued interest)
     outstanding: u128,
+    principal_outstanding: u128,
+    interest_accrued: u128,
     loans: Vec<LoanInfo>,
 }
 
 // update (not query) because we do cross-canister calls
 #[update]
 async fn get_summary(p: Principal) -> Summary {
-    let (repute_id, collateral_id) = STATE.with(|s| {
+    let (repute_id, collateral_id, oracle) = STATE.with(|s| {
         let st = s.borrow();
-        (st.repute, st.collateral)
+        (st.repute, st.collateral, st.oracle)
     });
 
-    let (level,): (u64,) = call(repute_id, "get_level", (p,))
-        .await
-        .unwrap_or((0_u64,));
-    let (collateral,): (u128,) = call(collateral_id, "get_collateral", (p,))
+    let (mut stale, mut missing) = (Vec::new(), Vec::new());
+    let level = call_dependency(repute_id, "get_level", (p,)).await.ok().map(|(l,): (u64,)| l);
+    if level.is_none() {
+        missing.push(SummaryField::Level);
+    }
+    let collateral = call_dependency(collateral_id, "get_collateral", (p,))
         .await
-        .unwrap_or((0_u128,));
+        .ok()
+        .map(|(c,): (u128,)| c);
+    if collateral.is_none() {
+        missing.push(SummaryField::Collateral);
+    }
 
-    let (registered, loans_vec, outstanding) = STATE.with(|s| {
-        let st = s.borrow();
-        let registered = st.users.contains(&p);
-        let mut loans: Vec<LoanInfo> = st
-            .loans
-            .values()
-            .filter(|l| l.borrower == p)
-            .map(|l| LoanInfo {
-                id: l.id,
-                amount: l.amount,
-                status: format!("{:?}", l.status),
-            })
-            .collect();
-        loans.sort_by_key(|li| li.id);
-        let outstanding: u128 = st
-            .loans
-            .values()
-            .filter(|l| l.borrower == p && l.status == LoanStatus::Active)
-            .map(|l| l.amount.saturating_sub(l.repaid))
-            .sum();
-        (registered, loans, outstanding)
-    });
+    // a stale price still values the collateral (flagged), but never loan health
+    let (valuation, collateral_value) = match fetch_price(oracle).await {
+        Ok(PriceCheck::Fresh(v)) => (Some(v), collateral.map(|c| v.value_of(c))),
+        Ok(PriceCheck::Stale(v)) => {
+            stale.push(SummaryField::CollateralValue);
+            (None, collateral.map(|c| v.value_of(c)))
+        }
+        Ok(PriceCheck::Missing) | Err(_) => (None, None),
+    };
+    if collateral_value.is_none() {
+        missing.push(SummaryField::CollateralValue);
+    }
+
+    let now = time();
+    let mut loans = store::borrower_loans(p);
+    let transitions = refresh_statuses(now, &mut loans);
+    let registered = store::is_registered(p);
+    let threshold = STATE.with(|s| s.borrow().cfg.liquidation_threshold_bps);
+    let loans_vec: Vec<LoanInfo> = loans
+        .iter()
+        .map(|l| loan_info(l, now, valuation, threshold))
+        .collect();
+    emit_transitions(transitions).await;
+
+    // settled loans report zero principal and interest, so a plain sum is enough
+    let (principal_outstanding, interest_accrued) =
+        loans_vec.iter().fold((0u128, 0u128), |(p, i), li| {
+            (
+                p.saturating_add(li.principal_outstanding),
+                i.saturating_add(li.interest_accrued),
+            )
+        });
 
     Summary {
         registered,
         level,
         collateral,
-        outstanding,
+        collateral_value,
+        stale,
+        missing,
+        outstanding: principal_outstanding.saturating_add(interest_accrued),
+        principal_outstanding,
+        interest_accrued,
         loans: loans_vec,
     }
 }
 
+fn loan_info(l: &Loan, now: u64, valuation: Option<Valuation>, threshold_bps: u32) -> LoanInfo {
+    let principal_outstanding = l.principal_outstanding();
+    let interest_accrued = l.interest_at(now);
+    // loans without locked collateral (migrated from the baseline) have no health to report
+    let has_health = l.status.is_open() && l.collateral_locked > 0;
+    let health_factor_bps = valuation.filter(|_| has_health).and_then(|v| {
+        health_factor_bps(
+            v.value_of(l.collateral_locked),
+            principal_outstanding.saturating_add(interest_accrued),
+            threshold_bps,
+        )
+    });
+    LoanInfo {
+        id: l.id,
+        amount: l.amount,
+        status: format!("{:?}", l.status),
+        apr_bps: l.apr_bps,
+        due_at_ns: l.due_at_ns,
+        next_due_at_ns: l.next_due_at(),
+        days_past_due: l.days_past_due(now),
+        principal_outstanding,
+        interest_accrued,
+        total_due: principal_outstanding.saturating_add(interest_accrued),
+        collateral_locked: l.collateral_locked,
+        health_factor_bps,
+        disbursement_block: l.disbursement_block,
+    }
+}
+
+/// Send collected funds back to the borrower (best-effort)
+async fn refund(ledger: Principal, from: &icrc::Account, to: Principal, amount: u128, loan_id: u128) {
+    let _ = icrc::transfer(
+        ledger,
+        icrc::TransferArg {
+            from_subaccount: from.subaccount.clone(),
+            to: to.into(),
+            amount,
+            fee: None,
+            memo: Some(loan_id.to_be_bytes().to_vec()),
+            created_at_time: None,
+        },
+    )
+    .await;
+}
+
+#[query]
+fn get_repayments(loan_id: u128) -> Result<Vec<Repayment>, Error> {
+    store::get_loan(loan_id).map(|l| l.repayments).ok_or(Error::LoanNotFound)
+}
+
+#[query]
+fn get_schedule(loan_id: u128) -> Result<Vec<Installment>, Error> {
+    store::get_loan(loan_id).map(|l| l.schedule).ok_or(Error::LoanNotFound)
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug)]
 struct Recommendation {
     decision: String,
@@ -175,146 +1095,1703 @@ struct Recommendation {
     reasons: Vec<String>,
 }
 
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct CollateralAccount {
+    free: u128,
+    locked: u128,
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug)]
 struct LoanDecision {
     loan_id: Option<u128>,
+    /// Set when the decision is REVIEW; poll with `get_application`
+    application_id: Option<u128>,
     decision: String,
     score: u64,
     reasons: Vec<String>,
 }
 
-#[update]
-async fn request_loan(amount: u128) -> LoanDecision {
-    if amount == 0 {
-        trap("amount must be > 0");
-    }
-    let me = caller();
-
-    // must be registered
-    let registered = STATE.with(|s| s.borrow().users.contains(&me));
-    if !registered {
-        trap("not registered");
-    }
-
-    let (rep_id, col_id, ai_id, bus) =
-        STATE.with(|s| {
-            let st = s.borrow();
-            (st.repute, st.collateral, st.trust_ai, st.event_bus)
-        });
+/// Client-chosen key making a call safe to resubmit, ICRC-1 style
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Idempotency {
+    key: Vec<u8>,
+    /// Client time (ns); must fall inside the dedup window
+    created_at_time: u64,
+}
 
-    let (level,): (u64,) = call(rep_id, "get_level", (me,))
-        .await
-        .unwrap_or((0_u64,));
-    let (collateral,): (u128,) = call(col_id, "get_collateral", (me,))
-        .await
-        .unwrap_or((0_u128,));
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct DedupEntry {
+    created_at_time: u64,
+    /// The call and arguments the key was first used with
+    fingerprint: String,
+    result: DedupResult,
+}
 
-    let (rec,): (Recommendation,) =
-        call(ai_id, "recommend", (me, collateral, level as u64))
-            .await
-            .map_err(|e| trap(&format!("trust_ai call failed: {e:?}")))
-            .unwrap();
-
-    // On APPROVE, open a loan
-    let (loan_id_opt, decision) = if rec.decision == "APPROVE" {
-        let id = STATE.with(|s| {
-            let mut st = s.borrow_mut();
-            let id = st.next_loan_id;
-            st.next_loan_id += 1;
-            st.loans.insert(
-                id,
-                Loan {
-                    id,
-                    borrower: me,
-                    amount,
-                    repaid: 0,
-                    status: LoanStatus::Active,
-                    created_at_ns: time(),
-                },
-            );
-            id
-        });
-        (Some(id), "APPROVE".to_string())
-    } else {
-        (None, rec.decision.clone())
-    };
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum DedupResult {
+    RequestLoan(LoanDecision),
+    Repay(RepayResult),
+}
 
-    // best-effort audit event
-    if let Some(bus) = bus {
-        let payload = json!({
-            "kind": "loans.request",
-            "actor": format!("{}", me),
-            "amount": amount,
-            "decision": decision,
-            "score": rec.score,
-            "reasons": rec.reasons,
-            "loan_id": loan_id_opt,
-        })
-        .to_string();
-        let _: Result<(), _> = ic_cdk::call(bus, "emit", (payload,)).await;
+/// ICRC-1 window check: at most `window` old and `drift` ahead of `now`
+fn check_created_at(created_at_time: u64, now: u64, cfg: &Config) -> Result<(), Error> {
+    let window_ns = cfg.dedup_window_secs.saturating_mul(1_000_000_000);
+    let drift_ns = cfg.dedup_drift_secs.saturating_mul(1_000_000_000);
+    if created_at_time.saturating_add(window_ns).saturating_add(drift_ns) < now {
+        return Err(Error::TooOld);
     }
+    if created_at_time > now.saturating_add(drift_ns) {
+        return Err(Error::CreatedInFuture { canister_time: now });
+    }
+    Ok(())
+}
 
-    LoanDecision {
-        loan_id: loan_id_opt,
-        decision,
-        score: rec.score,
-        reasons: rec.reasons,
+/// The stored result of an earlier call with the same key, if any. Reusing a
+/// key for a different call or different arguments is an error.
+fn find_duplicate(
+    me: Principal,
+    idempotency: Option<&Idempotency>,
+    fingerprint: &str,
+) -> Result<Option<DedupResult>, Error> {
+    let Some(idem) = idempotency else {
+        return Ok(None);
+    };
+    if idem.key.is_empty() || idem.key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+        return Err(Error::InvalidArgument(format!(
+            "idempotency key must be 1..={MAX_IDEMPOTENCY_KEY_LEN} bytes"
+        )));
     }
+    STATE.with(|s| {
+        let st = s.borrow();
+        check_created_at(idem.created_at_time, time(), &st.cfg)?;
+        match st.dedup.get(&(me, idem.key.clone())) {
+            Some(entry) if entry.fingerprint != fingerprint => Err(Error::InvalidArgument(
+                "idempotency key was already used with different arguments".into(),
+            )),
+            Some(entry) => Ok(Some(entry.result.clone())),
+            None => Ok(None),
+        }
+    })
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
+fn remember(
+    me: Principal,
+    idempotency: Option<Idempotency>,
+    fingerprint: String,
+    result: DedupResult,
+) {
+    let Some(idem) = idempotency else {
+        return;
+    };
+    STATE.with(|s| {
+        s.borrow_mut().dedup.insert(
+            (me, idem.key),
+            DedupEntry { created_at_time: idem.created_at_time, fingerprint, result },
+        );
+    })
+}
+
+/// Forget keys whose `created_at_time` has left the window; resubmitting
+/// them now fails with `TooOld` anyway
+fn prune_dedup(now: u64) {
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        let cfg = st.cfg.clone();
+        st.dedup.retain(|_, e| check_created_at(e.created_at_time, now, &cfg).is_ok());
+    })
+}
+
+/// Resubmitting with the same `idempotency` key inside the dedup window
+/// returns the first call's decision instead of opening another loan.
+#[update]
+async fn request_loan(
+    amount: u128,
+    term_days: Option<u32>,
+    plan: Option<RepaymentPlan>,
+    idempotency: Option<Idempotency>,
+) -> Result<LoanDecision, Error> {
+    request_loan_for(caller(), amount, term_days, plan, idempotency).await
+}
+
+async fn request_loan_for(
+    me: Principal,
+    amount: u128,
+    term_days: Option<u32>,
+    plan: Option<RepaymentPlan>,
+    idempotency: Option<Idempotency>,
+) -> Result<LoanDecision, Error> {
+    let _guard = CallerGuard::acquire(me)?;
+    let fingerprint = format!("request_loan:{amount}:{term_days:?}:{plan:?}");
+    if let Some(DedupResult::RequestLoan(decision)) =
+        find_duplicate(me, idempotency.as_ref(), &fingerprint)?
+    {
+        return Ok(decision);
+    }
+
+    let decision = evaluate_request(me, amount, term_days, plan).await?;
+    remember(me, idempotency, fingerprint, DedupResult::RequestLoan(decision.clone()));
+    Ok(decision)
+}
+
+async fn evaluate_request(
+    me: Principal,
+    amount: u128,
+    term_days: Option<u32>,
+    plan: Option<RepaymentPlan>,
+) -> Result<LoanDecision, Error> {
+    if amount == 0 {
+        return Err(Error::InvalidAmount("amount must be > 0".into()));
+    }
+
+    let term_days = STATE.with(|s| {
+        let cfg = &s.borrow().cfg;
+        let t = term_days.unwrap_or(cfg.default_term_days);
+        if t == 0 || t > cfg.max_term_days {
+            return Err(Error::InvalidArgument(format!(
+                "term_days must be in 1..={}",
+                cfg.max_term_days
+            )));
+        }
+        Ok(t)
+    })?;
+    let plan = plan.unwrap_or_default();
+    if plan.installments == 0 || plan.installments > term_days {
+        return Err(Error::InvalidArgument("installments must be in 1..=term_days".into()));
+    }
+    if amount < plan.installments as u128 {
+        return Err(Error::InvalidAmount(
+            "amount must cover at least one unit per installment".into(),
+        ));
+    }
+
+    // must be registered
+    let registered = store::is_registered(me);
+    if !registered {
+        return Err(Error::NotRegistered);
+    }
+
+    let ai_id = STATE.with(|s| s.borrow().trust_ai);
+
+    let assessment = assess(me, amount).await?;
+
+    let (rec,): (Recommendation,) =
+        call_dependency(
+            ai_id,
+            "recommend",
+            (me, assessment.collateral(), assessment.level, Some(amount), Some(term_days)),
+        )
+        .await?;
+    let mut reasons = rec.reasons;
+
+    let valuation = match assessment.ltv {
+        Ok((valuation, reason)) => {
+            reasons.push(reason);
+            Some(valuation)
+        }
+        Err(reason) => {
+            reasons.push(reason);
+            None
+        }
+    };
+
+    // On APPROVE (and within LTV), lock collateral and open a loan;
+    // REVIEW goes to the underwriting queue
+    let mut application_id = None;
+    let (loan_id_opt, decision) = match valuation {
+        None => (None, "REJECT".to_string()),
+        Some(valuation) if rec.decision == "APPROVE" => {
+            let free = assessment.account.free;
+            match open_loan(me, amount, term_days, &plan, free, assessment.max_ltv_bps, valuation)
+                .await
+            {
+                Ok(id) => (Some(id), "APPROVE".to_string()),
+                Err(e) => {
+                    reasons.push(e.to_string());
+                    (None, "REJECT".to_string())
+                }
+            }
+        }
+        Some(_) if rec.decision == "REVIEW" => {
+            application_id = Some(file_application(
+                me,
+                amount,
+                term_days,
+                plan.clone(),
+                rec.score,
+                reasons.clone(),
+            ));
+            (None, "REVIEW".to_string())
+        }
+        Some(_) => (None, rec.decision.clone()),
+    };
+
+    let details = LoansEvent::Requested {
+        amount,
+        term_days,
+        installments: plan.installments,
+        decision: decision.clone(),
+        score: rec.score,
+        reasons: reasons.clone(),
+        loan_id: loan_id_opt,
+        application_id,
+    };
+    emit_event(EventKind::LoanRequested, me, Some(me), details).await;
+
+    Ok(LoanDecision {
+        loan_id: loan_id_opt,
+        application_id,
+        decision,
+        score: rec.score,
+        reasons,
+    })
+}
+
+/// A REVIEW outcome waiting for an underwriter
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Application {
+    id: u128,
+    borrower: Principal,
+    amount: u128,
+    term_days: u32,
+    plan: RepaymentPlan,
+    score: u64,
+    reasons: Vec<String>,
+    created_at_ns: u64,
+    status: ApplicationStatus,
+    /// Underwriter who approved or rejected it
+    decided_by: Option<Principal>,
+    decided_at_ns: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+enum ApplicationStatus {
+    Pending,
+    /// An underwriter's approval is opening the loan
+    Approving,
+    Approved { loan_id: u128 },
+    Rejected { reason: String },
+}
+
+fn file_application(
+    borrower: Principal,
+    amount: u128,
+    term_days: u32,
+    plan: RepaymentPlan,
+    score: u64,
+    reasons: Vec<String>,
+) -> u128 {
+    let id = STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        let id = st.next_application_id;
+        st.next_application_id += 1;
+        id
+    });
+    store::put_application(Application {
+        id,
+        borrower,
+        amount,
+        term_days,
+        plan,
+        score,
+        reasons,
+        created_at_ns: time(),
+        status: ApplicationStatus::Pending,
+        decided_by: None,
+        decided_at_ns: None,
+    });
+    id
+}
+
+/// Borrower polling: visible to its borrower, underwriters and admin
+#[query]
+fn get_application(id: u128) -> Result<Application, Error> {
+    let me = caller();
+    let app = store::get_application(id).ok_or(Error::ApplicationNotFound)?;
+    if app.borrower != me && !STATE.with(|s| is_underwriter(&s.borrow(), me)) {
+        return Err(Error::Unauthorized("not your application".into()));
+    }
+    Ok(app)
+}
+
+#[query]
+fn my_applications() -> Vec<Application> {
+    let me = caller();
+    store::applications(|a| a.borrower == me)
+}
+
+#[query]
+fn list_applications(pending_only: bool) -> Result<Vec<Application>, Error> {
+    ensure_underwriter()?;
+    Ok(store::applications(|a| !pending_only || a.status == ApplicationStatus::Pending))
+}
+
+/// Re-assess a pending application and open its loan exactly as an APPROVE
+/// would. On failure the application goes back to Pending and the reason is
+/// returned as `Err` (a trap would discard that rollback).
+#[update]
+async fn approve_application(id: u128) -> Result<u128, Error> {
+    ensure_underwriter()?;
+    let me = caller();
+
+    // the approval opens a loan for the borrower, so it competes with their own calls
+    let mut app = store::get_application(id).ok_or(Error::ApplicationNotFound)?;
+    let _guard = CallerGuard::acquire(app.borrower)?;
+
+    // claim it before awaiting so a second approval can't open another loan
+    if app.status != ApplicationStatus::Pending {
+        return Err(Error::InvalidState("application is not pending".into()));
+    }
+    app.status = ApplicationStatus::Approving;
+    store::put_application(app.clone());
+
+    let opened = match assess(app.borrower, app.amount).await {
+        Ok(Assessment { ltv: Ok((valuation, _)), account, max_ltv_bps, .. }) => {
+            open_loan(
+                app.borrower,
+                app.amount,
+                app.term_days,
+                &app.plan,
+                account.free,
+                max_ltv_bps,
+                valuation,
+            )
+            .await
+        }
+        Ok(Assessment { ltv: Err(reason), .. }) => Err(Error::InvalidState(reason)),
+        Err(e) => Err(e),
+    };
+
+    let now = time();
+    if let Some(mut a) = store::get_application(id) {
+        match &opened {
+            Ok(loan_id) => {
+                a.status = ApplicationStatus::Approved { loan_id: *loan_id };
+                a.decided_by = Some(me);
+                a.decided_at_ns = Some(now);
+            }
+            Err(_) => a.status = ApplicationStatus::Pending,
+        }
+        store::put_application(a);
+    }
+    let loan_id = opened?;
+
+    emit_application_event(EventKind::ApplicationApproved, me, &app, Some(loan_id), None).await;
+    Ok(loan_id)
+}
+
+#[update]
+async fn reject_application(id: u128, reason: String) -> Result<(), Error> {
+    ensure_underwriter()?;
+    let me = caller();
+
+    let mut app = store::get_application(id).ok_or(Error::ApplicationNotFound)?;
+    if app.status != ApplicationStatus::Pending {
+        return Err(Error::InvalidState("application is not pending".into()));
+    }
+    app.status = ApplicationStatus::Rejected { reason: reason.clone() };
+    app.decided_by = Some(me);
+    app.decided_at_ns = Some(time());
+    store::put_application(app.clone());
+
+    emit_application_event(EventKind::ApplicationRejected, me, &app, None, Some(reason)).await;
+    Ok(())
+}
+
+async fn emit_application_event(
+    kind: EventKind,
+    actor: Principal,
+    app: &Application,
+    loan_id: Option<u128>,
+    reason: Option<String>,
+) {
+    let details = LoansEvent::ApplicationDecided {
+        application_id: app.id,
+        amount: app.amount,
+        loan_id,
+        reason,
+    };
+    emit_event(kind, actor, Some(app.borrower), details).await;
+}
+
+fn is_underwriter(st: &State, p: Principal) -> bool {
+    p == st.admin || st.underwriters.contains(&p)
+}
+
+fn ensure_underwriter() -> Result<(), Error> {
+    let c = caller();
+    STATE.with(|s| {
+        if is_underwriter(&s.borrow(), c) {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized("caller is not admin or underwriter".into()))
+        }
+    })
+}
+
+/// Reputation and collateral inputs for a prospective loan
+struct Assessment {
+    level: u64,
+    account: CollateralAccount,
+    max_ltv_bps: u32,
+    /// Valuation to size the lock with plus the LTV reason, or why the LTV check failed
+    ltv: Result<(Valuation, String), String>,
+}
+
+impl Assessment {
+    fn collateral(&self) -> u128 {
+        self.account.free.saturating_add(self.account.locked)
+    }
+}
+
+/// Fetch level, collateral and price, and check `amount` against the LTV limit.
+/// An unreachable repute or collateral canister is an error, never a zero.
+async fn assess(borrower: Principal, amount: u128) -> Result<Assessment, Error> {
+    let (rep_id, col_id, oracle) = STATE.with(|s| {
+        let st = s.borrow();
+        (st.repute, st.collateral, st.oracle)
+    });
+
+    let (level,): (u64,) = call_dependency(rep_id, "get_level", (borrower,)).await?;
+    let (account,): (CollateralAccount,) =
+        call_dependency(col_id, "get_account", (borrower,)).await?;
+    let collateral = account.free.saturating_add(account.locked);
+
+    // LTV limit applies to everything the borrower would owe, not just this loan
+    let max_ltv_bps = STATE.with(|s| s.borrow().cfg.max_ltv_bps(level));
+    let outstanding = outstanding_of(borrower, time());
+    // an oracle outage fails the call; a stale or missing price is a REJECT reason
+    let ltv = fetch_price(oracle).await?.fresh().and_then(|valuation| {
+        let value = valuation.value_of(collateral);
+        check_ltv(amount, outstanding, value, max_ltv_bps).map(|reason| (valuation, reason))
+    });
+
+    Ok(Assessment { level, account, max_ltv_bps, ltv })
+}
+
+/// Latest oracle price, as a valuation
+enum PriceCheck {
+    Fresh(Valuation),
+    /// Older than the oracle's `max_age_secs`
+    Stale(Valuation),
+    /// The oracle has no price yet
+    Missing,
+}
+
+impl PriceCheck {
+    /// The valuation if fresh, otherwise a human-readable reason
+    fn fresh(self) -> Result<Valuation, String> {
+        match self {
+            PriceCheck::Fresh(v) => Ok(v),
+            PriceCheck::Stale(_) => Err("collateral price is stale".into()),
+            PriceCheck::Missing => Err("no collateral price available".into()),
+        }
+    }
+}
+
+/// Read the latest price; without an oracle collateral is valued 1:1
+async fn fetch_price(oracle: Option<Principal>) -> Result<PriceCheck, Error> {
+    let Some(oracle) = oracle else {
+        return Ok(PriceCheck::Fresh(Valuation { price_e6: None }));
+    };
+    let (price,): (Option<PriceStatus>,) = call_dependency(oracle, "get_price", ()).await?;
+    Ok(match price {
+        Some(p) if !p.stale => PriceCheck::Fresh(Valuation { price_e6: Some(p.price_e6) }),
+        Some(p) => PriceCheck::Stale(Valuation { price_e6: Some(p.price_e6) }),
+        None => PriceCheck::Missing,
+    })
+}
+
+/// Fresh valuation or an error saying why there is none
+async fn fetch_valuation(oracle: Option<Principal>) -> Result<Valuation, Error> {
+    fetch_price(oracle).await?.fresh().map_err(Error::PriceUnavailable)
+}
+
+// native tests can't reach other canisters; they get a stub that suspends like a real call
+#[cfg(not(test))]
+use ic_cdk::call as dependency_call;
+#[cfg(test)]
+use tests::dependency_call;
+
+/// Call a dependency canister, retrying transient rejections (e.g. a full
+/// queue) up to `Config::dependency_retries` times. Never fabricates a reply.
+async fn call_dependency<A, R>(canister: Principal, method: &str, args: A) -> Result<R, Error>
+where
+    A: ArgumentEncoder + Clone,
+    R: for<'a> ArgumentDecoder<'a>,
+{
+    if canister == Principal::anonymous() {
+        return Err(Error::DependencyUnavailable(format!(
+            "{method}: dependency canister not configured"
+        )));
+    }
+    let retries = STATE.with(|s| s.borrow().cfg.dependency_retries);
+    let mut attempt = 0;
+    loop {
+        match dependency_call(canister, method, args.clone()).await {
+            Ok(reply) => return Ok(reply),
+            Err((code, _)) if should_retry(code, attempt, retries) => attempt += 1,
+            Err((code, msg)) => {
+                return Err(Error::DependencyUnavailable(format!(
+                    "{method} on {canister} failed after {} attempt(s): {code:?} {msg}",
+                    attempt + 1
+                )));
+            }
+        }
+    }
+}
+
+/// Only transient rejections are worth retrying; a trap or a missing method won't heal
+fn should_retry(code: RejectionCode, attempt: u32, retries: u32) -> bool {
+    code == RejectionCode::SysTransient && attempt < retries
+}
+
+/// Total still owed by `p` across open loans
+fn outstanding_of(p: Principal, now: u64) -> u128 {
+    store::open_loans(p)
+        .iter()
+        .map(|l| l.total_due_at(now))
+        .sum()
+}
+
+/// Check `amount` + `outstanding` against the LTV limit on `collateral_value`.
+/// Both outcomes carry a human-readable reason.
+fn check_ltv(
+    amount: u128,
+    outstanding: u128,
+    collateral_value: u128,
+    max_ltv_bps: u32,
+) -> Result<String, String> {
+    let limit = collateral_value.saturating_mul(max_ltv_bps as u128) / BPS_DENOM;
+    let total = amount.saturating_add(outstanding);
+    if total > limit {
+        Err(format!(
+            "requested {} + outstanding {} > borrow limit {} (max LTV {} bps)",
+            amount, outstanding, limit, max_ltv_bps
+        ))
+    } else {
+        Ok(format!(
+            "requested {} + outstanding {} ≤ borrow limit {} (max LTV {} bps)",
+            amount, outstanding, limit, max_ltv_bps
+        ))
+    }
+}
+
+/// Locked collateral value, discounted by the liquidation threshold, over
+/// the debt, in basis points. Below 10_000 the loan can be liquidated.
+fn health_factor_bps(collateral_value: u128, debt: u128, threshold_bps: u32) -> Option<u128> {
+    (debt > 0).then(|| collateral_value.saturating_mul(threshold_bps as u128) / debt)
+}
+
+/// Collateral units to lock so the new loan alone sits at `max_ltv_bps`
+fn required_collateral(amount: u128, max_ltv_bps: u32, valuation: Valuation) -> u128 {
+    let value = amount
+        .saturating_mul(BPS_DENOM)
+        .div_ceil(max_ltv_bps.max(1) as u128);
+    valuation.units_for(value)
+}
+
+/// Lock the borrower's collateral for a new loan id, record the loan and
+/// disburse the principal. Nothing is kept if the lock or the transfer fails.
+async fn open_loan(
+    borrower: Principal,
+    amount: u128,
+    term_days: u32,
+    plan: &RepaymentPlan,
+    free_collateral: u128,
+    max_ltv_bps: u32,
+    valuation: Valuation,
+) -> Result<u128, Error> {
+    let required = required_collateral(amount, max_ltv_bps, valuation);
+    let (id, col_id) = STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        let id = st.next_loan_id;
+        st.next_loan_id += 1;
+        (id, st.collateral)
+    });
+    if free_collateral < required {
+        return Err(Error::InsufficientCollateral { free: free_collateral, required });
+    }
+
+    let res: Result<(Result<(), CollateralError>,), _> =
+        call(col_id, "lock", (borrower, id, required)).await;
+    match res {
+        Ok((Ok(()),)) => {}
+        Ok((Err(CollateralError::InsufficientCollateral { free, .. }),)) => {
+            return Err(Error::InsufficientCollateral { free, required });
+        }
+        Ok((Err(e),)) => {
+            return Err(Error::DependencyUnavailable(format!("collateral lock failed: {e:?}")));
+        }
+        Err((_, msg)) => {
+            return Err(Error::DependencyUnavailable(format!("collateral lock failed: {msg}")));
+        }
+    }
+
+    let now = time();
+    let (apr_bps, ledger) = STATE.with(|s| (s.borrow().cfg.apr_bps, s.borrow().ledger));
+    let term_ns = term_days as u64 * NANOS_PER_DAY;
+    store::put_loan(Loan {
+        id,
+        borrower,
+        amount,
+        repaid: 0,
+        status: LoanStatus::Active,
+        created_at_ns: now,
+        apr_bps,
+        principal_repaid: 0,
+        interest_paid: 0,
+        interest_accrued: 0,
+        accrued_at_ns: now,
+        due_at_ns: now.saturating_add(term_ns),
+        schedule: build_schedule(amount, apr_bps, now, term_ns, plan),
+        collateral_locked: required,
+        liquidation: None,
+        disbursement_block: None,
+        repayments: Vec::new(),
+        paid_late: false,
+    });
+
+    let Some(ledger) = ledger else {
+        return Ok(id);
+    };
+    let transfer = icrc::transfer(
+        ledger,
+        icrc::TransferArg {
+            from_subaccount: None,
+            to: borrower.into(),
+            amount,
+            fee: None,
+            memo: Some(id.to_be_bytes().to_vec()),
+            created_at_time: Some(now),
+        },
+    )
+    .await;
+    match transfer {
+        Ok(block) => {
+            store::update_loan(id, |l| l.disbursement_block = Some(block));
+            Ok(id)
+        }
+        Err(e) => {
+            // roll back: drop the loan and free the collateral
+            store::remove_loan(id);
+            release_collateral(id).await;
+            Err(Error::TransferFailed(format!("disbursement failed: {e}")))
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
 struct RepayResult {
     repaid: u128,
+    /// Total still due (principal + accrued interest)
     remaining: u128,
     status: String,
+    principal_outstanding: u128,
+    interest_accrued: u128,
+    /// Cumulative split of `repaid`
+    principal_repaid: u128,
+    interest_paid: u128,
+    /// Ledger block that collected this payment (None without a ledger)
+    block_index: Option<u128>,
+    /// Part of the requested amount above what was due
+    excess: u128,
+    /// Part of `excess` added to the borrower's credit (Credit policy)
+    credited: u128,
+    /// Earlier credit spent on this payment
+    credit_used: u128,
+    credit_balance: u128,
 }
 
+/// How a repayment is funded and where each part goes
+#[derive(Debug, PartialEq, Eq)]
+struct Settlement {
+    /// Applied to the loan
+    applied: u128,
+    excess: u128,
+    credited: u128,
+    /// Taken from the borrower's credit
+    from_credit: u128,
+    /// Pulled from the borrower's ledger allowance
+    collect: u128,
+}
+
+/// Split a payment of `amount` against `owed`; credit only ever pays debt
+fn settle(
+    amount: u128,
+    owed: u128,
+    credit: u128,
+    policy: OverpaymentPolicy,
+) -> Result<Settlement, Error> {
+    let applied = amount.min(owed);
+    let excess = amount - applied;
+    let credited = match policy {
+        OverpaymentPolicy::Reject if excess > 0 => {
+            return Err(Error::InvalidAmount(format!(
+                "amount {amount} exceeds the {owed} still due"
+            )));
+        }
+        OverpaymentPolicy::Credit => excess,
+        _ => 0,
+    };
+    let from_credit = credit.min(applied);
+    let collect = applied - from_credit + credited;
+    Ok(Settlement { applied, excess, credited, from_credit, collect })
+}
+
+#[query]
+fn get_credit(p: Principal) -> u128 {
+    store::credit_of(p)
+}
+
+/// What `p`'s open loans need from their collateral; `collateral_backend`
+/// uses it to keep `withdraw` from leaving a loan under-collateralised.
+#[query]
+fn get_collateral_needs(p: Principal) -> CollateralNeeds {
+    let now = time();
+    let debts = store::open_loans(p)
+        .iter()
+        .map(|l| LoanDebt { loan_id: l.id, debt: l.total_due_at(now) })
+        .collect();
+    let liquidation_threshold_bps = STATE.with(|s| s.borrow().cfg.liquidation_threshold_bps);
+    CollateralNeeds { debts, liquidation_threshold_bps }
+}
+
+/// Resubmitting with the same `idempotency` key inside the dedup window
+/// returns the first call's result instead of collecting the payment twice.
 #[update]
-async fn repay(loan_id: u128, amount: u128) -> RepayResult {
+async fn repay(
+    loan_id: u128,
+    amount: u128,
+    idempotency: Option<Idempotency>,
+) -> Result<RepayResult, Error> {
+    repay_for(caller(), loan_id, amount, idempotency).await
+}
+
+async fn repay_for(
+    me: Principal,
+    loan_id: u128,
+    amount: u128,
+    idempotency: Option<Idempotency>,
+) -> Result<RepayResult, Error> {
+    let _guard = CallerGuard::acquire(me)?;
+    let fingerprint = format!("repay:{loan_id}:{amount}");
+    if let Some(DedupResult::Repay(result)) =
+        find_duplicate(me, idempotency.as_ref(), &fingerprint)?
+    {
+        return Ok(result);
+    }
+
+    let result = apply_repayment(me, loan_id, amount).await?;
+    remember(me, idempotency, fingerprint, DedupResult::Repay(result.clone()));
+    Ok(result)
+}
+
+async fn apply_repayment(me: Principal, loan_id: u128, amount: u128) -> Result<RepayResult, Error> {
     if amount == 0 {
-        trap("amount must be > 0");
+        return Err(Error::InvalidAmount("amount must be > 0".into()));
     }
-    let me = caller();
+    // only the borrower's own calls may move the loan through overdue/default
+    let mut loan = store::get_loan(loan_id).ok_or(Error::LoanNotFound)?;
+    if loan.borrower != me {
+        return Err(Error::Unauthorized("only borrower can repay".into()));
+    }
+    let now = time();
 
-    let (event_bus, result) = STATE.with(|s| {
-        let mut st = s.borrow_mut();
+    // overdue/default transitions still apply to the loan being repaid
+    let transitions = refresh_statuses(now, std::slice::from_mut(&mut loan));
 
-        // read bus BEFORE taking a mutable ref to the loan (fixes E0502)
-        let bus = st.event_bus;
+    // validate before any funds move; credit spent here is put back on failure
+    let validated = STATE.with(|s| {
+        let st = s.borrow();
+        let l = store::get_loan(loan_id).ok_or(Error::LoanNotFound)?;
+        if !l.status.is_open() {
+            return Err(Error::InvalidState("loan already closed".into()));
+        }
+        let credit = store::credit_of(me);
+        let settlement = settle(amount, l.total_due_at(now), credit, st.cfg.overpayment_policy)?;
+        store::set_credit(me, credit - settlement.from_credit);
+        let treasury = icrc::Account {
+            owner: ic_cdk::id(),
+            subaccount: st.treasury_subaccount.clone(),
+        };
+        Ok((st.ledger, treasury, settlement))
+    });
+    emit_transitions(transitions).await;
+    let (ledger, treasury, settlement) = validated?;
+    let restore_credit = || store::set_credit(me, store::credit_of(me) + settlement.from_credit);
 
-        let l = st
-            .loans
-            .get_mut(&loan_id)
-            .unwrap_or_else(|| trap("loan not found"));
+    // with a ledger, pull the funds from the borrower's ICRC-2 allowance first;
+    // the loan is only credited once the ledger confirms
+    let block_index = match ledger {
+        Some(_) if settlement.collect == 0 => None,
+        Some(ledger) => {
+            let pulled = icrc::transfer_from(
+                ledger,
+                icrc::TransferFromArgs {
+                    spender_subaccount: None,
+                    from: me.into(),
+                    to: treasury.clone(),
+                    amount: settlement.collect,
+                    fee: None,
+                    memo: Some(loan_id.to_be_bytes().to_vec()),
+                    created_at_time: Some(now),
+                },
+            )
+            .await;
+            match pulled {
+                Ok(block) => Some(block),
+                Err(e) => {
+                    restore_credit();
+                    return Err(Error::TransferFailed(e));
+                }
+            }
+        }
+        None => None,
+    };
 
-        if l.borrower != me {
-            trap("only borrower can repay");
+    let applied = STATE.with(|s| {
+        let st = s.borrow();
+        let cfg = st.cfg.clone();
+        let credit_balance = store::credit_of(me) + settlement.credited;
+
+        let mut l = store::get_loan(loan_id).expect("validated above");
+        // closed (e.g. liquidated) while the ledger call was in flight
+        if !l.status.is_open() {
+            return None;
         }
-        if l.status != LoanStatus::Active {
-            trap("loan not active");
+
+        let late = (l.status == LoanStatus::Overdue).then(|| l.days_past_due(now));
+        l.paid_late |= l.status != LoanStatus::Active;
+        l.apply_payment(settlement.applied, now);
+        l.repayments.push(Repayment { amount: settlement.applied, at_ns: now, block_index });
+        // catching up on missed installments lifts Overdue back to Active
+        l.refresh_status(now, &cfg);
+        let result = RepayResult {
+            repaid: l.repaid,
+            remaining: l.principal_outstanding().saturating_add(l.interest_accrued),
+            status: format!("{:?}", l.status),
+            principal_outstanding: l.principal_outstanding(),
+            interest_accrued: l.interest_accrued,
+            principal_repaid: l.principal_repaid,
+            interest_paid: l.interest_paid,
+            block_index,
+            excess: settlement.excess,
+            credited: settlement.credited,
+            credit_used: settlement.from_credit,
+            credit_balance,
+        };
+        let repaid_in_full = l.status == LoanStatus::Repaid;
+        let paid_late = l.paid_late;
+        store::put_loan(l);
+        store::set_credit(me, credit_balance);
+
+        let rep_event = match late {
+            Some(days_past_due) => Some(ReputationEvent::LatePayment { days_past_due }),
+            None if repaid_in_full && !paid_late => Some(ReputationEvent::RepaidOnTime),
+            None => None,
+        };
+        Some((repaid_in_full, rep_event, result))
+    });
+
+    let Some((repaid_in_full, rep_event, result)) = applied else {
+        restore_credit();
+        if let (Some(ledger), Some(_)) = (ledger, block_index) {
+            refund(ledger, &treasury, me, settlement.collect, loan_id).await;
+        }
+        return Err(Error::InvalidState(
+            "loan closed while the repayment was in flight; funds returned".into(),
+        ));
+    };
+
+    if repaid_in_full {
+        release_collateral(loan_id).await;
+    }
+    if let Some(event) = rep_event {
+        adjust_reputation(me, loan_id, event).await;
+    }
+
+    let details = LoansEvent::Repaid {
+        loan_id,
+        amount,
+        applied: settlement.applied,
+        excess: settlement.excess,
+        credited: settlement.credited,
+    };
+    emit_event(EventKind::LoanRepaid, me, Some(me), details).await;
+
+    Ok(result)
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct SeizeResult {
+    seized: u128,
+    returned: u128,
+}
+
+/// Collateral units to seize and the debt they cover, if `l` can be liquidated
+fn liquidation_terms(
+    l: &Loan,
+    now: u64,
+    valuation: Valuation,
+    cfg: &Config,
+) -> Result<(u128, u128), Error> {
+    if !l.status.is_open() {
+        return Err(Error::InvalidState("loan already closed".into()));
+    }
+    // loans migrated from the baseline build never locked any
+    if l.collateral_locked == 0 {
+        return Err(Error::InvalidState("loan has no locked collateral to seize".into()));
+    }
+    let debt = l.total_due_at(now);
+    let health = health_factor_bps(
+        valuation.value_of(l.collateral_locked),
+        debt,
+        cfg.liquidation_threshold_bps,
+    );
+    let undercollateralized = health.is_some_and(|h| h < BPS_DENOM);
+    if l.status != LoanStatus::Defaulted && !undercollateralized {
+        return Err(Error::InvalidState(
+            "loan is neither defaulted nor below the liquidation threshold".into(),
+        ));
+    }
+
+    let penalty = debt.saturating_mul(cfg.liquidation_penalty_bps as u128) / BPS_DENOM;
+    Ok((valuation.units_for(debt.saturating_add(penalty)), debt))
+}
+
+/// Close a Defaulted or undercollateralized loan by seizing its locked
+/// collateral: debt plus penalty goes to the liquidator (or to this canister
+/// when the admin liquidates on behalf of the protocol), the rest back to the borrower.
+/// A failed seize reopens the loan and is returned as `Err` (a trap would
+/// discard the rollback).
+#[update]
+async fn liquidate(loan_id: u128) -> Result<Liquidation, Error> {
+    let me = caller();
+    let now = time();
+    let (col_id, oracle, recipient) = STATE.with(|s| {
+        let st = s.borrow();
+        if me != st.admin && !st.liquidators.contains(&me) {
+            return Err(Error::Unauthorized("caller is not admin or liquidator".into()));
         }
+        let recipient = if me == st.admin { ic_cdk::id() } else { me };
+        Ok((st.collateral, st.oracle, recipient))
+    })?;
+
+    // emitted up front: a default saved here must be reported (and penalised)
+    // even when the liquidation itself is refused or fails below
+    let transitions = refresh_statuses(now, store::get_loan(loan_id).as_mut_slice());
+    emit_transitions(transitions).await;
+    let valuation = fetch_valuation(oracle).await?;
 
-        l.repaid = l.repaid.saturating_add(amount);
-        if l.repaid >= l.amount {
-            l.status = LoanStatus::Repaid;
+    // close the loan before awaiting the seize so a concurrent repay sees it closed
+    let (previous, seize_units, debt) = STATE.with(|s| {
+        let cfg = s.borrow().cfg.clone();
+        let mut l = store::get_loan(loan_id).ok_or(Error::LoanNotFound)?;
+        let (seize_units, debt) = liquidation_terms(&l, now, valuation, &cfg)?;
+        let previous = l.clone();
+        l.accrue(now);
+        l.status = LoanStatus::Liquidated;
+        store::put_loan(l);
+        Ok((previous, seize_units, debt))
+    })?;
+
+    let res: Result<(Result<SeizeResult, CollateralError>,), _> =
+        call(col_id, "seize", (loan_id, seize_units, recipient)).await;
+    let seized = match res
+        .map_err(|(_, msg)| msg)
+        .and_then(|(r,)| r.map_err(|e| format!("{e:?}")))
+    {
+        Ok(r) => r,
+        Err(msg) => {
+            store::put_loan(previous);
+            return Err(Error::DependencyUnavailable(format!("collateral seize failed: {msg}")));
         }
-        let remaining = l.amount.saturating_sub(l.repaid);
-        let status = format!("{:?}", l.status);
+    };
+
+    let liquidation = Liquidation {
+        at_ns: now,
+        liquidator: me,
+        recipient,
+        debt,
+        seized: seized.seized,
+        returned: seized.returned,
+    };
+    let borrower = store::update_loan(loan_id, |l| {
+        l.collateral_locked = 0;
+        l.liquidation = Some(liquidation.clone());
+        l.borrower
+    })
+    .expect("loan vanished");
+
+    let details = LoansEvent::Liquidated {
+        loan_id,
+        debt,
+        seized: liquidation.seized,
+        returned: liquidation.returned,
+        recipient,
+    };
+    emit_event(EventKind::LoanLiquidated, me, Some(borrower), details).await;
 
-        (bus, RepayResult { repaid: l.repaid, remaining, status })
+    Ok(liquidation)
+}
+
+/// Deployment wiring and parameters, as returned by `get_config`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct LoansConfig {
+    admin: Principal,
+    repute: Principal,
+    collateral: Principal,
+    trust_ai: Principal,
+    event_bus: Option<Principal>,
+    oracle: Option<Principal>,
+    ledger: Option<Principal>,
+    treasury_subaccount: Option<Vec<u8>>,
+    liquidators: Vec<Principal>,
+    underwriters: Vec<Principal>,
+    params: Config,
+}
+
+#[query]
+fn get_config() -> LoansConfig {
+    STATE.with(|s| {
+        let st = s.borrow();
+        let mut liquidators: Vec<Principal> = st.liquidators.iter().copied().collect();
+        liquidators.sort();
+        let mut underwriters: Vec<Principal> = st.underwriters.iter().copied().collect();
+        underwriters.sort();
+        LoansConfig {
+            admin: st.admin,
+            repute: st.repute,
+            collateral: st.collateral,
+            trust_ai: st.trust_ai,
+            event_bus: st.event_bus,
+            oracle: st.oracle,
+            ledger: st.ledger,
+            treasury_subaccount: st.treasury_subaccount.clone(),
+            liquidators,
+            underwriters,
+            params: st.cfg.clone(),
+        }
+    })
+}
+
+/// Hand admin rights to another principal; the caller loses them
+#[update]
+async fn set_admin(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    if p == Principal::anonymous() {
+        return Err(Error::InvalidArgument("admin cannot be anonymous".into()));
+    }
+    let old = STATE.with(|s| std::mem::replace(&mut s.borrow_mut().admin, p));
+    emit_config_event("admin", Some(old), Some(p)).await;
+    Ok(())
+}
+
+#[update]
+async fn set_repute(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    let old = STATE.with(|s| std::mem::replace(&mut s.borrow_mut().repute, p));
+    emit_config_event("repute", Some(old), Some(p)).await;
+    Ok(())
+}
+
+#[update]
+async fn set_collateral(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    let old = STATE.with(|s| std::mem::replace(&mut s.borrow_mut().collateral, p));
+    emit_config_event("collateral", Some(old), Some(p)).await;
+    Ok(())
+}
+
+#[update]
+async fn set_trust_ai(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    let old = STATE.with(|s| std::mem::replace(&mut s.borrow_mut().trust_ai, p));
+    emit_config_event("trust_ai", Some(old), Some(p)).await;
+    Ok(())
+}
+
+/// `None` stops event emission; the change itself is reported to the new bus
+#[update]
+async fn set_event_bus(p: Option<Principal>) -> Result<(), Error> {
+    ensure_admin()?;
+    let old = STATE.with(|s| std::mem::replace(&mut s.borrow_mut().event_bus, p));
+    emit_config_event("event_bus", old, p).await;
+    Ok(())
+}
+
+/// `None` falls back to 1:1 (mock) collateral valuation
+#[update]
+async fn set_oracle(p: Option<Principal>) -> Result<(), Error> {
+    ensure_admin()?;
+    let old = STATE.with(|s| std::mem::replace(&mut s.borrow_mut().oracle, p));
+    emit_config_event("oracle", old, p).await;
+    Ok(())
+}
+
+/// Only affects new disbursements and repayments; `None` stops moving funds
+#[update]
+async fn set_ledger(p: Option<Principal>) -> Result<(), Error> {
+    ensure_admin()?;
+    let old = STATE.with(|s| std::mem::replace(&mut s.borrow_mut().ledger, p));
+    emit_config_event("ledger", old, p).await;
+    Ok(())
+}
+
+/// Replace the lending parameters (LTV tiers, liquidation, reputation steps,
+/// dedup window, …) without a reinstall; rejected unless they validate
+#[update]
+async fn set_config(cfg: Config) -> Result<(), Error> {
+    ensure_admin()?;
+    let new = format!("{cfg:?}");
+    let old = replace_config(cfg)?;
+    emit_config_event("params", Some(format!("{old:?}")), Some(new)).await;
+    Ok(())
+}
+
+fn replace_config(cfg: Config) -> Result<Config, Error> {
+    validate_config(&cfg).map_err(Error::InvalidArgument)?;
+    Ok(STATE.with(|s| std::mem::replace(&mut s.borrow_mut().cfg, cfg)))
+}
+
+#[update]
+async fn set_overpayment_policy(policy: OverpaymentPolicy) -> Result<(), Error> {
+    ensure_admin()?;
+    let old = STATE.with(|s| {
+        std::mem::replace(&mut s.borrow_mut().cfg.overpayment_policy, policy)
     });
+    emit_config_event("overpayment_policy", Some(old), Some(policy)).await;
+    Ok(())
+}
 
-    // best-effort audit event
-    if let Some(bus) = event_bus {
-        let payload = json!({
-            "kind": "loans.repay",
-            "actor": format!("{}", me),
-            "loan_id": loan_id,
-            "amount": amount,
-        })
-        .to_string();
-        let _: Result<(), _> = ic_cdk::call(bus, "emit", (payload,)).await;
+#[update]
+async fn add_liquidator(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    if STATE.with(|s| s.borrow_mut().liquidators.insert(p)) {
+        emit_config_event("liquidators", None, Some(p)).await;
+    }
+    Ok(())
+}
+
+#[update]
+async fn remove_liquidator(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    if STATE.with(|s| s.borrow_mut().liquidators.remove(&p)) {
+        emit_config_event("liquidators", Some(p), None).await;
     }
+    Ok(())
+}
 
-    result
+#[update]
+async fn add_underwriter(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    if STATE.with(|s| s.borrow_mut().underwriters.insert(p)) {
+        emit_config_event("underwriters", None, Some(p)).await;
+    }
+    Ok(())
+}
+
+#[update]
+async fn remove_underwriter(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    if STATE.with(|s| s.borrow_mut().underwriters.remove(&p)) {
+        emit_config_event("underwriters", Some(p), None).await;
+    }
+    Ok(())
+}
+
+// one audit event per change
+async fn emit_config_event<T: fmt::Display>(field: &str, old: Option<T>, new: Option<T>) {
+    let details = LoansEvent::ConfigChanged {
+        field: field.to_string(),
+        old: old.map(|v| v.to_string()),
+        new: new.map(|v| v.to_string()),
+    };
+    emit_event(EventKind::LoansConfigChanged, caller(), None, details).await;
+}
+
+fn ensure_admin() -> Result<(), Error> {
+    let c = caller();
+    STATE.with(|s| {
+        if c == s.borrow().admin {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized("caller is not admin".into()))
+        }
+    })
+}
+
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use icroots_valuation::SATS_PER_BTC;
+
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    #[test]
+    fn did_file_matches_the_rust_interface() {
+        use candid_parser::utils::{service_equal, CandidSource};
+        let did = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("loans_backend.did");
+        service_equal(CandidSource::Text(&__export_service()), CandidSource::File(&did))
+            .unwrap_or_else(|e| panic!("loans_backend.did is out of date: {e}"));
+    }
+
+    const DAY_NS: u64 = NANOS_PER_DAY;
+
+    fn loan(amount: u128, apr_bps: u32) -> Loan {
+        Loan {
+            id: 1,
+            borrower: Principal::anonymous(),
+            amount,
+            repaid: 0,
+            status: LoanStatus::Active,
+            created_at_ns: 0,
+            apr_bps,
+            principal_repaid: 0,
+            interest_paid: 0,
+            interest_accrued: 0,
+            accrued_at_ns: 0,
+            due_at_ns: 30 * DAY_NS,
+            schedule: build_schedule(amount, apr_bps, 0, 30 * DAY_NS, &RepaymentPlan::default()),
+            collateral_locked: 0,
+            liquidation: None,
+            disbursement_block: None,
+            repayments: Vec::new(),
+            paid_late: false,
+        }
+    }
+
+    fn plan(style: Amortization, installments: u32) -> RepaymentPlan {
+        RepaymentPlan { style, installments }
+    }
+
+    #[test]
+    fn accrues_simple_interest_over_time() {
+        let l = loan(1_000_000, 1_000);
+        assert_eq!(l.interest_at(0), 0);
+        assert_eq!(l.interest_at(365 * DAY_NS), 100_000);
+    }
+
+    #[test]
+    fn payments_settle_interest_before_principal() {
+        let mut l = loan(1_000_000, 1_000);
+        l.apply_payment(150_000, 365 * DAY_NS);
+        assert_eq!(l.interest_paid, 100_000);
+        assert_eq!(l.principal_repaid, 50_000);
+        assert_eq!(l.interest_accrued, 0);
+        assert_eq!(l.status, LoanStatus::Active);
+
+        l.apply_payment(950_000, 365 * DAY_NS);
+        assert_eq!(l.principal_outstanding(), 0);
+        assert_eq!(l.status, LoanStatus::Repaid);
+    }
+
+    #[test]
+    fn moves_to_overdue_then_defaulted_after_grace() {
+        let cfg = Config::default();
+        let mut l = loan(1_000, 1_000);
+        assert_eq!(l.refresh_status(33 * DAY_NS, &cfg), None);
+        assert_eq!(l.refresh_status(34 * DAY_NS, &cfg), Some(LoanStatus::Overdue));
+        assert_eq!(l.days_past_due(34 * DAY_NS), 4);
+        assert_eq!(l.refresh_status(61 * DAY_NS, &cfg), Some(LoanStatus::Defaulted));
+        assert_eq!(l.refresh_status(90 * DAY_NS, &cfg), None);
+    }
+
+    #[test]
+    fn equal_principal_schedule_splits_principal_evenly() {
+        let sched = build_schedule(
+            1_200,
+            1_200,
+            0,
+            360 * DAY_NS,
+            &plan(Amortization::EqualPrincipal, 12),
+        );
+        assert_eq!(sched.len(), 12);
+        assert!(sched.iter().all(|i| i.principal == 100));
+        assert!(sched[0].interest > sched[11].interest);
+        assert_eq!(sched[11].due_at_ns, 360 * DAY_NS);
+    }
+
+    #[test]
+    fn annuity_schedule_has_level_payments() {
+        let sched = build_schedule(
+            1_000_000,
+            1_200,
+            0,
+            360 * DAY_NS,
+            &plan(Amortization::Annuity, 12),
+        );
+        assert_eq!(sched.iter().map(|i| i.principal).sum::<u128>(), 1_000_000);
+        let payments: Vec<u128> = sched.iter().map(|i| i.principal + i.interest).collect();
+        let (min, max) = (payments.iter().min().unwrap(), payments.iter().max().unwrap());
+        // rounding on each installment is absorbed by the last one
+        assert!(max - min <= 12, "payments drift: {payments:?}");
+    }
+
+    #[test]
+    fn payments_settle_oldest_installment_first() {
+        let mut l = loan(1_000, 0);
+        l.schedule = build_schedule(1_000, 0, 0, 30 * DAY_NS, &plan(Amortization::EqualPrincipal, 4));
+        l.apply_payment(300, DAY_NS);
+        assert!(l.schedule[0].paid_at_ns.is_some());
+        assert_eq!(l.schedule[1].principal_paid, 50);
+        assert!(l.schedule[1].paid_at_ns.is_none());
+        assert_eq!(l.next_due_at(), l.schedule[1].due_at_ns);
+    }
+
+    #[test]
+    fn max_ltv_follows_reputation_tiers() {
+        let cfg = Config::default();
+        assert!(validate_config(&cfg).is_ok());
+        assert_eq!(cfg.max_ltv_bps(0), 3_000);
+        assert_eq!(cfg.max_ltv_bps(79), 5_000);
+        assert_eq!(cfg.max_ltv_bps(500), 6_500);
+    }
+
+    #[test]
+    fn config_changes_are_validated() {
+        let bad = Config { liquidation_threshold_bps: 0, ..Config::default() };
+        assert!(matches!(replace_config(bad), Err(Error::InvalidArgument(_))));
+        assert_eq!(get_config().params.liquidation_threshold_bps, 8_000);
+
+        let tiers = vec![LtvTier { min_level: 0, max_ltv_bps: 4_000 }];
+        let cfg = Config { ltv_tiers: tiers, dedup_window_secs: 60, ..Config::default() };
+        assert_eq!(replace_config(cfg).unwrap().dedup_window_secs, 24 * 60 * 60);
+        let params = get_config().params;
+        assert_eq!((params.max_ltv_bps(500), params.dedup_window_secs), (4_000, 60));
+    }
+
+    #[test]
+    fn ltv_check_counts_existing_outstanding() {
+        assert!(check_ltv(30_000, 0, 100_000, 3_000).is_ok());
+        assert!(check_ltv(20_000, 10_001, 100_000, 3_000).is_err());
+        let mock = Valuation { price_e6: None };
+        assert_eq!(required_collateral(30_000, 3_000, mock), 100_000);
+    }
+
+    #[test]
+    fn valuation_converts_sats_at_oracle_price() {
+        // 60,000 USD/BTC
+        let v = Valuation { price_e6: Some(60_000_000_000) };
+        assert_eq!(v.value_of(SATS_PER_BTC / 2), 30_000_000_000);
+        assert_eq!(v.units_for(30_000_000_000), SATS_PER_BTC / 2);
+        // 3,000 USD at 30% LTV needs 10,000 USD of BTC
+        assert_eq!(required_collateral(3_000_000_000, 3_000, v), SATS_PER_BTC / 6 + 1);
+    }
+
+    #[test]
+    fn health_factor_crosses_threshold() {
+        // 10k collateral at 80% threshold covers 8k of debt
+        assert_eq!(health_factor_bps(10_000, 8_000, 8_000), Some(10_000));
+        assert!(health_factor_bps(10_000, 8_001, 8_000).unwrap() < 10_000);
+        assert_eq!(health_factor_bps(10_000, 0, 8_000), None);
+    }
+
+    #[test]
+    fn loans_without_locked_collateral_are_never_liquidated() {
+        let (cfg, v) = (Config::default(), Valuation { price_e6: None });
+        let locked = Loan { collateral_locked: 10_000, ..loan(9_000, 0) };
+        assert_eq!(liquidation_terms(&locked, 0, v, &cfg).unwrap().1, 9_000);
+        assert_eq!(loan_info(&locked, 0, Some(v), 8_000).health_factor_bps, Some(8_888));
+
+        // a baseline loan: open, in default, but nothing to seize
+        let baseline = Loan { status: LoanStatus::Defaulted, ..loan(9_000, 0) };
+        let err = liquidation_terms(&baseline, 0, v, &cfg).unwrap_err();
+        assert_eq!(err, Error::InvalidState("loan has no locked collateral to seize".into()));
+        assert_eq!(loan_info(&baseline, 0, Some(v), 8_000).health_factor_bps, None);
+    }
+
+    #[test]
+    fn reputation_steps_follow_config() {
+        let cfg = Config::default();
+        assert_eq!(ReputationEvent::RepaidOnTime.delta(&cfg), 5);
+        assert_eq!(ReputationEvent::LatePayment { days_past_due: 4 }.delta(&cfg), -3);
+        assert_eq!(ReputationEvent::Defaulted.delta(&cfg), -20);
+        assert_eq!(
+            ReputationEvent::LatePayment { days_past_due: 4 }.reason(7),
+            "late payment on loan 7 (4 days past due)"
+        );
+    }
+
+    #[test]
+    fn errors_render_readable_reasons() {
+        let e = Error::InsufficientCollateral { free: 10, required: 25 };
+        assert_eq!(e.to_string(), "free collateral 10 < required 25");
+        let e = Error::Unauthorized("caller is not admin".into());
+        assert_eq!(e.to_string(), "unauthorized: caller is not admin");
+    }
+
+    #[test]
+    fn retries_only_transient_rejections_within_budget() {
+        assert!(should_retry(RejectionCode::SysTransient, 0, 2));
+        assert!(should_retry(RejectionCode::SysTransient, 1, 2));
+        assert!(!should_retry(RejectionCode::SysTransient, 2, 2));
+        assert!(!should_retry(RejectionCode::CanisterError, 0, 2));
+        assert!(!should_retry(RejectionCode::DestinationInvalid, 0, 2));
+    }
+
+    /// Stands in for an inter-canister call: pending on the first poll
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+        fn poll(mut self: std::pin::Pin<&mut Self>, _: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Test stand-in for `ic_cdk::call`: suspends once, then rejects
+    pub(super) async fn dependency_call<A, R>(
+        _canister: Principal,
+        method: &str,
+        _args: A,
+    ) -> Result<R, (RejectionCode, String)> {
+        YieldOnce(false).await;
+        Err((RejectionCode::CanisterError, format!("{method}: stubbed")))
+    }
+
+    async fn guarded_call(p: Principal) -> Result<(), Error> {
+        let _guard = CallerGuard::acquire(p)?;
+        YieldOnce(false).await;
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_calls_from_one_borrower_are_rejected() {
+        let alice = Principal::from_slice(&[1]);
+        let bob = Principal::from_slice(&[2]);
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let mut first = pin!(guarded_call(alice));
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        // while the first call awaits, a second one from alice fails fast...
+        let second = pin!(guarded_call(alice)).poll(&mut cx);
+        assert_eq!(second, Poll::Ready(Err(Error::CallInProgress)));
+        // ...but other borrowers are unaffected
+        let mut other = pin!(guarded_call(bob));
+        assert!(other.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(first.as_mut().poll(&mut cx), Poll::Ready(Ok(())));
+        assert_eq!(other.as_mut().poll(&mut cx), Poll::Ready(Ok(())));
+        let mut third = pin!(guarded_call(alice));
+        assert!(third.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(third.as_mut().poll(&mut cx), Poll::Ready(Ok(())));
+    }
+
+    #[test]
+    fn repay_is_rejected_while_a_loan_request_is_in_flight() {
+        let alice = Principal::from_slice(&[4]);
+        STATE.with(|s| s.borrow_mut().repute = Principal::from_slice(&[9]));
+        store::register(alice);
+        let mut cx = Context::from_waker(Waker::noop());
+
+        // request_loan is now awaiting repute's get_level
+        let mut request = pin!(request_loan_for(alice, 100, None, None, None));
+        assert!(request.as_mut().poll(&mut cx).is_pending());
+
+        let repay = pin!(repay_for(alice, 1, 10, None)).poll(&mut cx);
+        assert!(matches!(repay, Poll::Ready(Err(Error::CallInProgress))));
+
+        let Poll::Ready(result) = request.as_mut().poll(&mut cx) else {
+            panic!("request_loan should finish once the call returns");
+        };
+        assert!(matches!(result, Err(Error::DependencyUnavailable(_))));
+        // the guard is gone, so repay runs its own checks again
+        let repay = pin!(repay_for(alice, 1, 0, None)).poll(&mut cx);
+        assert!(matches!(repay, Poll::Ready(Err(Error::InvalidAmount(_)))));
+    }
+
+    #[test]
+    fn only_the_borrower_can_repay_or_refresh_a_loan() {
+        let (bob, mallory) = (Principal::from_slice(&[5]), Principal::from_slice(&[6]));
+        // long past due, but the refresh must not run for a stranger's call
+        store::put_loan(Loan { id: 40, borrower: bob, due_at_ns: 1, ..loan(1_000, 0) });
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let repay = pin!(repay_for(mallory, 40, 10, None)).poll(&mut cx);
+        assert!(matches!(repay, Poll::Ready(Err(Error::Unauthorized(_)))));
+        assert_eq!(store::get_loan(40).map(|l| l.status), Some(LoanStatus::Active));
+        let repay = pin!(repay_for(mallory, 41, 10, None)).poll(&mut cx);
+        assert!(matches!(repay, Poll::Ready(Err(Error::LoanNotFound))));
+    }
+
+    #[test]
+    fn guard_is_released_when_the_call_unwinds() {
+        let carol = Principal::from_slice(&[3]);
+        let result = std::panic::catch_unwind(|| {
+            let _guard = CallerGuard::acquire(carol).unwrap();
+            panic!("trap after await");
+        });
+        assert!(result.is_err());
+        assert!(CallerGuard::acquire(carol).is_ok());
+    }
+
+    #[test]
+    fn created_at_time_must_fall_inside_the_dedup_window() {
+        let cfg = Config { dedup_window_secs: 100, dedup_drift_secs: 10, ..Config::default() };
+        let sec = 1_000_000_000;
+        let now = 1_000 * sec;
+        assert_eq!(check_created_at(now, now, &cfg), Ok(()));
+        assert_eq!(check_created_at(now - 110 * sec, now, &cfg), Ok(()));
+        assert_eq!(check_created_at(now - 111 * sec, now, &cfg), Err(Error::TooOld));
+        assert_eq!(check_created_at(now + 10 * sec, now, &cfg), Ok(()));
+        assert_eq!(
+            check_created_at(now + 11 * sec, now, &cfg),
+            Err(Error::CreatedInFuture { canister_time: now })
+        );
+    }
+
+    #[test]
+    fn overpayments_follow_the_policy() {
+        use OverpaymentPolicy::*;
+        assert!(matches!(settle(150, 100, 0, Reject), Err(Error::InvalidAmount(_))));
+        let exact = settle(100, 100, 0, Reject).unwrap();
+        assert_eq!((exact.applied, exact.excess, exact.collect), (100, 0, 100));
+
+        let capped = settle(150, 100, 0, Cap).unwrap();
+        assert_eq!((capped.applied, capped.excess, capped.collect), (100, 50, 100));
+        assert_eq!(capped.credited, 0);
+
+        let credited = settle(150, 100, 0, Credit).unwrap();
+        assert_eq!((credited.applied, credited.credited, credited.collect), (100, 50, 150));
+
+        // existing credit pays debt first, never funds a new credit
+        let spent = settle(60, 100, 40, Credit).unwrap();
+        assert_eq!((spent.applied, spent.from_credit, spent.collect), (60, 40, 20));
+    }
+
+    /// What the baseline build with `loans` loans over `borrowers` borrowers
+    /// wrote via `stable_save`; every tenth loan is repaid
+    fn baseline_snapshot(loans: u128, borrowers: u8) -> Vec<u8> {
+        let borrower = |i: u128| Principal::from_slice(&[(i % borrowers as u128) as u8]);
+        let baseline = StateV0 {
+            admin: Principal::from_slice(&[0xad]),
+            repute: Principal::anonymous(),
+            collateral: Principal::anonymous(),
+            trust_ai: Principal::anonymous(),
+            event_bus: None,
+            next_loan_id: loans + 1,
+            users: (0..borrowers as u128).map(borrower).collect(),
+            loans: (1..=loans)
+                .map(|id| {
+                    let status = if id % 10 == 0 { LoanStatus::Repaid } else { LoanStatus::Active };
+                    let repaid = if id % 10 == 0 { 1_000 + id } else { 0 };
+                    let amount = 1_000 + id;
+                    let borrower = borrower(id);
+                    (id, LoanV0 { id, borrower, amount, repaid, status, created_at_ns: 0 })
+                })
+                .collect(),
+        };
+        candid::encode_one(&baseline).unwrap()
+    }
+
+    #[test]
+    fn baseline_state_migrates_into_stable_maps() {
+        let st: State = icroots_state::restore_snapshot(&baseline_snapshot(3_000, 30)).unwrap();
+
+        assert_eq!((st.admin, st.next_loan_id), (Principal::from_slice(&[0xad]), 3_001));
+        assert_eq!(store::loan_count(), 3_000);
+        let borrower = Principal::from_slice(&[3]);
+        let loans = store::borrower_loans(borrower);
+        assert_eq!(loans.len(), 100);
+        assert!(loans.iter().all(|l| l.borrower == borrower && l.amount == 1_000 + l.id));
+        assert!(store::is_registered(borrower));
+    }
+
+    #[test]
+    fn baseline_open_loans_are_indexed_for_the_sweep() {
+        // the baseline kept no open-loan index; migration builds it
+        icroots_state::restore_snapshot::<State>(&baseline_snapshot(1_200, 7)).unwrap();
+        assert_eq!(store::open_loans_after(None, usize::MAX).len(), 1_080);
+        assert_eq!(store::open_loans(Principal::from_slice(&[3])).len(), 154);
+
+        // long past the default term: the sweep finds every open loan
+        let now = u64::MAX / 2;
+        let (mut cursor, mut pages, mut defaulted) = (None, 0, 0);
+        loop {
+            let (transitions, next) = sweep_page(now, cursor);
+            pages += 1;
+            defaulted += transitions.len();
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!((pages, defaulted), (3, 1_080));
+        assert!(sweep_page(now, None).0.is_empty());
+    }
+
+    #[test]
+    fn upgrades_only_save_the_small_state() {
+        let st: State = icroots_state::restore_snapshot(&baseline_snapshot(2_000, 20)).unwrap();
+        let saved_len = candid::encode_one(&st).unwrap().len();
+
+        // pre_upgrade/post_upgrade round trip with many more loans stored
+        let newcomer = Principal::from_slice(&[0xff]);
+        for id in 2_001..=6_000 {
+            store::put_loan(Loan { id, borrower: newcomer, ..loan(1_000, 1_000) });
+        }
+        store::save_state(&st);
+        let saved = store::saved_state();
+        assert_eq!((saved.version, saved.state.len()), (STATE_VERSION, saved_len));
+        let restored = saved.restore::<State>().unwrap();
+        assert_eq!(restored.next_loan_id, st.next_loan_id);
+        assert_eq!(store::loan_count(), 6_000);
+        assert_eq!(store::borrower_loans(newcomer).len(), 4_000);
+    }
+
+    #[test]
+    fn baseline_snapshot_migrates_to_current_version() {
+        let st: State =
+            icroots_state::restore_snapshot(include_bytes!("../snapshots/state_v0.bin")).unwrap();
+
+        let p = |i: u8| Principal::from_slice(&[i]);
+        assert_eq!((st.admin, st.repute, st.event_bus), (p(0xad), p(0xa1), Some(p(0xeb))));
+        assert_eq!((st.next_loan_id, st.next_application_id), (3, 1));
+        assert!(store::is_registered(p(1)) && store::is_registered(p(2)));
+        let open = store::get_loan(1).unwrap();
+        assert_eq!((&open.status, open.apr_bps), (&LoanStatus::Active, 0));
+        assert_eq!((open.repaid, open.principal_outstanding()), (400, 600));
+        assert_eq!(open.schedule.len(), 1);
+        assert_eq!(store::get_loan(2).map(|l| l.status), Some(LoanStatus::Repaid));
+        assert_eq!(store::open_loans(p(2)).len(), 0);
+    }
+
+    #[test]
+    fn sweep_pages_through_open_loans_only() {
+        let owner = |id: u128| Principal::from_slice(&[(id % 7) as u8]);
+        for id in 1..=1_200 {
+            let status = if id % 4 == 0 { LoanStatus::Repaid } else { LoanStatus::Active };
+            store::put_loan(Loan { id, borrower: owner(id), status, ..loan(1_000, 1_000) });
+        }
+        assert_eq!(store::open_loans(owner(1)).len(), 129);
+
+        // long past due: every open loan defaults, one page at a time
+        let now = u64::MAX / 2;
+        let (mut cursor, mut pages, mut defaulted) = (None, 0, 0);
+        loop {
+            let (transitions, next) = sweep_page(now, cursor);
+            pages += 1;
+            defaulted += transitions.len();
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!((pages, defaulted), (2, 900));
+        assert!(sweep_page(now, None).0.is_empty());
+
+        // closing a loan drops it from the index
+        store::update_loan(1, |l| l.status = LoanStatus::Repaid);
+        assert_eq!(store::open_loans(owner(1)).len(), 128);
+    }
 }
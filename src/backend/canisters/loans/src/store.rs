@@ -0,0 +1,165 @@
+//! Stable-memory layout: the large maps live in `StableBTreeMap`s so upgrades
+//! don't have to serialize them; only the small `State` is saved on upgrade.
+
+use crate::{Application, Loan, State};
+use candid::Principal;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use icroots_state::{candid_storable, Persisted};
+use std::cell::RefCell;
+use std::ops::Bound;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const STATE_MEMORY: MemoryId = MemoryId::new(0);
+const LOANS_MEMORY: MemoryId = MemoryId::new(1);
+const BORROWER_LOANS_MEMORY: MemoryId = MemoryId::new(2);
+const APPLICATIONS_MEMORY: MemoryId = MemoryId::new(3);
+const USERS_MEMORY: MemoryId = MemoryId::new(4);
+const CREDITS_MEMORY: MemoryId = MemoryId::new(5);
+const OPEN_LOANS_MEMORY: MemoryId = MemoryId::new(6);
+
+candid_storable!(Loan, Application);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+    /// `State` as of the last upgrade; the live copy is the heap `STATE`
+    static SAVED_STATE: RefCell<StableCell<Persisted, Memory>> = RefCell::new(
+        StableCell::init(memory(STATE_MEMORY), Persisted::new(&State::default()))
+            .expect("init state cell"),
+    );
+    static LOANS: RefCell<StableBTreeMap<u128, Loan, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(LOANS_MEMORY)));
+    /// (borrower, loan id) index so per-borrower reads don't scan every loan
+    static BORROWER_LOANS: RefCell<StableBTreeMap<(Principal, u128), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(BORROWER_LOANS_MEMORY)));
+    /// (borrower, loan id) of loans that are still open; the sweep and the
+    /// balance checks walk this instead of every loan ever made
+    static OPEN_LOANS: RefCell<StableBTreeMap<(Principal, u128), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(OPEN_LOANS_MEMORY)));
+    static APPLICATIONS: RefCell<StableBTreeMap<u128, Application, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(APPLICATIONS_MEMORY)));
+    static USERS: RefCell<StableBTreeMap<Principal, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(USERS_MEMORY)));
+    /// Overpayments credited under `OverpaymentPolicy::Credit`, by borrower
+    static CREDITS: RefCell<StableBTreeMap<Principal, u128, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(CREDITS_MEMORY)));
+}
+
+fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+pub fn save_state(st: &State) {
+    SAVED_STATE.with(|c| icroots_state::save(c, st));
+}
+
+pub fn saved_state() -> Persisted {
+    SAVED_STATE.with(|c| c.borrow().get().clone())
+}
+
+pub fn get_loan(id: u128) -> Option<Loan> {
+    LOANS.with(|m| m.borrow().get(&id))
+}
+
+pub fn put_loan(loan: Loan) {
+    let key = (loan.borrower, loan.id);
+    BORROWER_LOANS.with(|m| m.borrow_mut().insert(key, ()));
+    OPEN_LOANS.with(|m| {
+        if loan.status.is_open() {
+            m.borrow_mut().insert(key, ());
+        } else {
+            m.borrow_mut().remove(&key);
+        }
+    });
+    LOANS.with(|m| m.borrow_mut().insert(loan.id, loan));
+}
+
+pub fn remove_loan(id: u128) {
+    if let Some(loan) = LOANS.with(|m| m.borrow_mut().remove(&id)) {
+        BORROWER_LOANS.with(|m| m.borrow_mut().remove(&(loan.borrower, id)));
+        OPEN_LOANS.with(|m| m.borrow_mut().remove(&(loan.borrower, id)));
+    }
+}
+
+/// Apply `f` to a stored loan and write it back; None if it doesn't exist
+pub fn update_loan<R>(id: u128, f: impl FnOnce(&mut Loan) -> R) -> Option<R> {
+    let mut loan = get_loan(id)?;
+    let out = f(&mut loan);
+    put_loan(loan);
+    Some(out)
+}
+
+pub fn borrower_loans(borrower: Principal) -> Vec<Loan> {
+    let ids: Vec<u128> = BORROWER_LOANS.with(|m| {
+        m.borrow()
+            .range((borrower, 0)..=(borrower, u128::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    ids.into_iter().filter_map(get_loan).collect()
+}
+
+/// Open loans of `borrower`
+pub fn open_loans(borrower: Principal) -> Vec<Loan> {
+    let ids: Vec<u128> = OPEN_LOANS.with(|m| {
+        m.borrow()
+            .range((borrower, 0)..=(borrower, u128::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    ids.into_iter().filter_map(get_loan).collect()
+}
+
+/// Up to `limit` open loans, in (borrower, id) order, after `cursor`
+pub fn open_loans_after(cursor: Option<(Principal, u128)>, limit: usize) -> Vec<Loan> {
+    let start = cursor.map_or(Bound::Unbounded, Bound::Excluded);
+    let ids: Vec<u128> = OPEN_LOANS.with(|m| {
+        m.borrow()
+            .range((start, Bound::Unbounded))
+            .take(limit)
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    ids.into_iter().filter_map(get_loan).collect()
+}
+
+#[cfg(test)]
+pub fn loan_count() -> u64 {
+    LOANS.with(|m| m.borrow().len())
+}
+
+pub fn get_application(id: u128) -> Option<Application> {
+    APPLICATIONS.with(|m| m.borrow().get(&id))
+}
+
+pub fn put_application(app: Application) {
+    APPLICATIONS.with(|m| m.borrow_mut().insert(app.id, app));
+}
+
+pub fn applications(filter: impl Fn(&Application) -> bool) -> Vec<Application> {
+    APPLICATIONS.with(|m| m.borrow().iter().map(|(_, a)| a).filter(|a| filter(a)).collect())
+}
+
+pub fn is_registered(p: Principal) -> bool {
+    USERS.with(|m| m.borrow().contains_key(&p))
+}
+
+pub fn register(p: Principal) {
+    USERS.with(|m| m.borrow_mut().insert(p, ()));
+}
+
+pub fn credit_of(p: Principal) -> u128 {
+    CREDITS.with(|m| m.borrow().get(&p).unwrap_or(0))
+}
+
+pub fn set_credit(p: Principal, balance: u128) {
+    CREDITS.with(|m| {
+        if balance == 0 {
+            m.borrow_mut().remove(&p);
+        } else {
+            m.borrow_mut().insert(p, balance);
+        }
+    });
+}
@@ -0,0 +1,256 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::{caller, time};
+use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
+use icroots_state::Versioned;
+use icroots_valuation::PriceStatus;
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+/// Persisted layout (see `icroots_state`); 1 is the first deployed one
+const STATE_VERSION: u32 = 1;
+
+/// Feed parameters (tunable at deploy/init time)
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Config {
+    /// A price older than this is reported as stale
+    max_age_secs: u64,
+    /// Samples kept for TWAP; the oldest are dropped first
+    max_samples: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_age_secs: 60 * 60, // demo default: relayer pushes at least hourly
+            max_samples: 500,
+        }
+    }
+}
+
+/// BTC/USD price, USD per 1 BTC with 6 decimals
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct PriceSample {
+    price_e6: u64,
+    timestamp_ns: u64,
+    relayer: Principal,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct State {
+    admin: Principal,
+    /// Principals allowed to push prices
+    relayers: HashSet<Principal>,
+    /// Accepted samples, oldest first
+    samples: VecDeque<PriceSample>,
+    cfg: Config,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            admin: Principal::anonymous(),
+            relayers: HashSet::new(),
+            samples: VecDeque::new(),
+            cfg: Config::default(),
+        }
+    }
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct InitArgs {
+    /// Optional admin override; defaults to deployer
+    admin: Option<Principal>,
+    /// Initial relayers (e.g. a local test identity)
+    relayers: Option<Vec<Principal>>,
+    max_age_secs: Option<u64>,
+    max_samples: Option<u32>,
+}
+
+#[init]
+fn init(args: Option<InitArgs>) {
+    let me = caller();
+    let args = args.unwrap_or_default();
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        st.admin = args.admin.unwrap_or(me);
+        st.relayers.extend(args.relayers.unwrap_or_default());
+        if let Some(v) = args.max_age_secs { st.cfg.max_age_secs = v; }
+        if let Some(v) = args.max_samples { st.cfg.max_samples = v.max(1); }
+    });
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    STATE.with(|s| icroots_state::save_to_stable_memory(&*s.borrow()));
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    let st = icroots_state::restore_from_stable_memory();
+    STATE.with(|s| *s.borrow_mut() = st);
+}
+
+impl Versioned for State {
+    const VERSION: u32 = STATE_VERSION;
+
+    fn migrate_step(from: u32, _bytes: &[u8]) -> Result<Vec<u8>, String> {
+        Err(format!("no migration from state version {from}"))
+    }
+}
+
+/// Failure reasons returned by updates
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+enum Error {
+    Unauthorized(String),
+    InvalidPrice(String),
+    InvalidTimestamp(String),
+}
+
+/// Record a relayer's price observed at `timestamp_ns`
+#[update]
+fn push_price(price_e6: u64, timestamp_ns: u64) -> Result<(), Error> {
+    let me = caller();
+    let now = time();
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        if !st.relayers.contains(&me) {
+            return Err(Error::Unauthorized("caller is not a relayer".into()));
+        }
+        if price_e6 == 0 {
+            return Err(Error::InvalidPrice("price must be > 0".into()));
+        }
+        if timestamp_ns > now {
+            return Err(Error::InvalidTimestamp("timestamp is in the future".into()));
+        }
+        if st.samples.back().is_some_and(|last| timestamp_ns <= last.timestamp_ns) {
+            return Err(Error::InvalidTimestamp(
+                "timestamp must be newer than the latest sample".into(),
+            ));
+        }
+        if st.samples.len() >= st.cfg.max_samples as usize {
+            st.samples.pop_front();
+        }
+        st.samples.push_back(PriceSample { price_e6, timestamp_ns, relayer: me });
+        Ok(())
+    })
+}
+
+/// Latest price with its staleness; `None` until the first push
+#[query]
+fn get_price() -> Option<PriceStatus> {
+    let now = time();
+    STATE.with(|s| {
+        let st = s.borrow();
+        st.samples.back().map(|last| {
+            let age_secs = now.saturating_sub(last.timestamp_ns) / NANOS_PER_SEC;
+            PriceStatus {
+                price_e6: last.price_e6,
+                timestamp_ns: last.timestamp_ns,
+                age_secs,
+                stale: age_secs > st.cfg.max_age_secs,
+            }
+        })
+    })
+}
+
+#[query]
+fn is_stale() -> bool {
+    get_price().is_none_or(|p| p.stale)
+}
+
+/// Time-weighted average price over the last `window_secs`
+#[query]
+fn twap(window_secs: u64) -> Option<u64> {
+    let window_ns = window_secs.saturating_mul(NANOS_PER_SEC);
+    STATE.with(|s| time_weighted_average(&s.borrow().samples, time(), window_ns))
+}
+
+#[query]
+fn list_relayers() -> Vec<Principal> {
+    STATE.with(|s| s.borrow().relayers.iter().copied().collect())
+}
+
+#[update]
+fn add_relayer(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    STATE.with(|s| {
+        s.borrow_mut().relayers.insert(p);
+    });
+    Ok(())
+}
+
+#[update]
+fn remove_relayer(p: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+    STATE.with(|s| {
+        s.borrow_mut().relayers.remove(&p);
+    });
+    Ok(())
+}
+
+/// Each sample counts for as long as it was the latest price inside the window;
+/// a sample older than the window counts from the window start.
+fn time_weighted_average(samples: &VecDeque<PriceSample>, now: u64, window_ns: u64) -> Option<u64> {
+    let start = now.saturating_sub(window_ns);
+    let (mut weighted, mut total) = (0u128, 0u128);
+    for (i, sample) in samples.iter().enumerate() {
+        let from = sample.timestamp_ns.max(start);
+        let until = samples.get(i + 1).map_or(now, |next| next.timestamp_ns);
+        if until <= from {
+            continue;
+        }
+        let dt = (until - from) as u128;
+        weighted += sample.price_e6 as u128 * dt;
+        total += dt;
+    }
+    if total == 0 {
+        // zero-length window: fall back to the latest price
+        return samples.back().map(|s| s.price_e6);
+    }
+    Some((weighted / total) as u64)
+}
+
+fn ensure_admin() -> Result<(), Error> {
+    let c = caller();
+    STATE.with(|s| {
+        if c == s.borrow().admin {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized("caller is not admin".into()))
+        }
+    })
+}
+
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn did_file_matches_the_rust_interface() {
+        use candid_parser::utils::{service_equal, CandidSource};
+        let did = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("oracle_backend.did");
+        service_equal(CandidSource::Text(&__export_service()), CandidSource::File(&did))
+            .unwrap_or_else(|e| panic!("oracle_backend.did is out of date: {e}"));
+    }
+
+    fn sample(price_e6: u64, timestamp_ns: u64) -> PriceSample {
+        PriceSample { price_e6, timestamp_ns, relayer: Principal::anonymous() }
+    }
+
+    #[test]
+    fn twap_weights_prices_by_duration() {
+        let samples: VecDeque<_> = vec![sample(100, 0), sample(200, 30), sample(400, 90)].into();
+        // window [0, 100): 100 for 30, 200 for 60, 400 for 10
+        assert_eq!(time_weighted_average(&samples, 100, 100), Some(190));
+        // window [80, 100): 200 for 10, 400 for 10
+        assert_eq!(time_weighted_average(&samples, 100, 20), Some(300));
+        assert_eq!(time_weighted_average(&VecDeque::new(), 100, 20), None);
+    }
+}
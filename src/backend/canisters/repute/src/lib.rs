@@ -1,18 +1,32 @@
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::{api::caller, call, trap};
-use ic_cdk::storage::{stable_restore, stable_save};
+use ic_cdk::api::{caller, time};
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
-use serde_json::json;
+use icroots_events::{Event, EventKind, Payload, ReputeEvent};
+use icroots_state::{candid_storable, decode, encode, Persisted, Versioned};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 
+/// Level changes kept per principal by `get_history`
+const MAX_HISTORY: usize = 50;
+/// Persisted layout (see `icroots_state`).
+/// 0: the first deployed `State` (levels only);
+/// 1: levels and history in stable maps
+const STATE_VERSION: u32 = 1;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const STATE_MEMORY: MemoryId = MemoryId::new(0);
+const LEVELS_MEMORY: MemoryId = MemoryId::new(1);
+const HISTORY_MEMORY: MemoryId = MemoryId::new(2);
+
+/// Small settings kept on the heap; levels and history live in stable maps
 #[derive(CandidType, Deserialize, Clone, Debug)]
 struct State {
     admin: Principal,
     /// Principals allowed to call `set_level` (e.g., loans canister)
     allowed_setters: HashSet<Principal>,
-    /// Reputation levels
-    levels: HashMap<Principal, u64>,
     /// Optional event bus canister to emit audit events
     event_bus: Option<Principal>,
 }
@@ -22,14 +36,76 @@ impl Default for State {
         Self {
             admin: Principal::anonymous(),
             allowed_setters: HashSet::new(),
-            levels: HashMap::new(),
             event_bus: None,
         }
     }
 }
 
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct LevelChange {
+    previous: u64,
+    level: u64,
+    reason: Option<String>,
+    actor: Principal,
+    at_ns: u64,
+}
+
+/// Failure reasons returned by updates
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+enum Error {
+    Unauthorized(String),
+    InvalidArgument(String),
+}
+
+/// Most recent level changes of one principal, oldest first
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct History(VecDeque<LevelChange>);
+
+/// Version 0 `State`, as `stable_save`d by the first deployed build
+#[derive(CandidType, Deserialize)]
+struct StateV0 {
+    admin: Principal,
+    allowed_setters: HashSet<Principal>,
+    levels: HashMap<Principal, u64>,
+    event_bus: Option<Principal>,
+}
+
+candid_storable!(History);
+
 thread_local! {
     static STATE: RefCell<State> = RefCell::new(State::default());
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+    /// `State` as of the last upgrade
+    static SAVED_STATE: RefCell<StableCell<Persisted, Memory>> = RefCell::new(
+        StableCell::init(memory(STATE_MEMORY), Persisted::new(&State::default()))
+            .expect("init state cell"),
+    );
+    /// Reputation levels
+    static LEVELS: RefCell<StableBTreeMap<Principal, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(LEVELS_MEMORY)));
+    static HISTORY: RefCell<StableBTreeMap<Principal, History, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(HISTORY_MEMORY)));
+}
+
+fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+/// v0 → v1: levels move to a stable map; level changes weren't recorded
+/// yet, so history starts empty
+fn migrate_v0(old: StateV0) -> State {
+    LEVELS.with(|m| {
+        let mut levels = m.borrow_mut();
+        for (p, level) in old.levels {
+            levels.insert(p, level);
+        }
+    });
+    State {
+        admin: old.admin,
+        allowed_setters: old.allowed_setters,
+        event_bus: old.event_bus,
+    }
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug, Default)]
@@ -56,56 +132,158 @@ fn init(args: Option<InitArgs>) {
     });
 }
 
+/// Levels and history live in stable memory already; only `State` is saved
 #[pre_upgrade]
 fn pre_upgrade() {
-    let st = STATE.with(|s| s.borrow().clone());
-    stable_save((st,)).expect("stable_save failed");
+    STATE.with(|s| SAVED_STATE.with(|c| icroots_state::save(c, &*s.borrow())));
 }
 
 #[post_upgrade]
 fn post_upgrade() {
-    let (st,): (State,) = stable_restore().unwrap_or((
-        State {
-            admin: caller(),
-            ..State::default()
-        },
-    ));
+    let st = icroots_state::restore_or_trap(|| SAVED_STATE.with(|c| c.borrow().get().clone()));
     STATE.with(|s| *s.borrow_mut() = st);
 }
 
+impl Versioned for State {
+    const VERSION: u32 = STATE_VERSION;
+
+    fn migrate_step(from: u32, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        match from {
+            0 => encode(&migrate_v0(decode(bytes)?)),
+            _ => Err(format!("no migration from state version {from}")),
+        }
+    }
+}
+
 #[query]
 fn get_level(p: Principal) -> u64 {
-    STATE.with(|s| *s.borrow().levels.get(&p).unwrap_or(&0))
+    LEVELS.with(|m| m.borrow().get(&p).unwrap_or(0))
+}
+
+#[query]
+fn get_history(p: Principal) -> Vec<LevelChange> {
+    HISTORY.with(|m| m.borrow().get(&p).map(|h| h.0.into()).unwrap_or_default())
 }
 
 #[update]
-async fn set_level(p: Principal, level: u64) {
-    ensure_can_set().unwrap_or_else(|e| trap(&e));
-    STATE.with(|s| {
-        s.borrow_mut().levels.insert(p, level);
+async fn set_level(p: Principal, level: u64, reason: Option<String>) -> Result<(), Error> {
+    ensure_can_set()?;
+    let previous = record_level(p, level, reason.clone());
+    emit_level_event(p, previous, level, reason).await;
+    Ok(())
+}
+
+/// Move `p`'s level by `delta` without leaving `floor..=cap`; a level already
+/// outside the bounds is never pushed further out. Returns the new level.
+#[update]
+async fn adjust_level(
+    p: Principal,
+    delta: i64,
+    floor: u64,
+    cap: u64,
+    reason: String,
+) -> Result<u64, Error> {
+    ensure_can_set()?;
+    if floor > cap {
+        return Err(Error::InvalidArgument("floor must be <= cap".into()));
+    }
+    let current = get_level(p);
+    let level = adjusted_level(current, delta, floor, cap);
+    if level != current {
+        record_level(p, level, Some(reason.clone()));
+        emit_level_event(p, current, level, Some(reason)).await;
+    }
+    Ok(level)
+}
+
+fn adjusted_level(current: u64, delta: i64, floor: u64, cap: u64) -> u64 {
+    let step = delta.unsigned_abs();
+    if delta >= 0 {
+        current.max(current.saturating_add(step).min(cap))
+    } else {
+        current.min(current.saturating_sub(step).max(floor))
+    }
+}
+
+/// Store the new level and its history entry; returns the previous level
+fn record_level(p: Principal, level: u64, reason: Option<String>) -> u64 {
+    let actor = caller();
+    let at_ns = time();
+    let previous = LEVELS.with(|m| m.borrow_mut().insert(p, level)).unwrap_or(0);
+    HISTORY.with(|m| {
+        let mut map = m.borrow_mut();
+        let mut history = map.get(&p).unwrap_or_default();
+        history.0.push_back(LevelChange { previous, level, reason, actor, at_ns });
+        while history.0.len() > MAX_HISTORY {
+            history.0.pop_front();
+        }
+        map.insert(p, history);
     });
+    previous
+}
 
-    // best-effort event emission
+// best-effort event emission
+async fn emit_level_event(p: Principal, previous: u64, level: u64, reason: Option<String>) {
     if let Some(bus) = STATE.with(|s| s.borrow().event_bus) {
-        let payload = json!({
-            "kind": "repute.set_level",
-            "actor": format!("{}", caller()),
-            "principal": format!("{}", p),
-            "level": level,
-        })
-        .to_string();
-        let _: Result<(), _> = call(bus, "emit", (payload,)).await;
+        let payload = Payload::Repute(ReputeEvent::LevelChanged { previous, level, reason });
+        let event = Event::new(EventKind::LevelChanged, caller(), Some(p), payload);
+        icroots_events::emit(bus, event).await;
     }
 }
 
-fn ensure_can_set() -> Result<(), String> {
+fn ensure_can_set() -> Result<(), Error> {
     let c = caller();
     STATE.with(|s| {
         let st = s.borrow();
         if c == st.admin || st.allowed_setters.contains(&c) {
             Ok(())
         } else {
-            Err("unauthorized: caller is not admin or allowed setter".into())
+            Err(Error::Unauthorized("caller is not admin or allowed setter".into()))
         }
     })
 }
+
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn did_file_matches_the_rust_interface() {
+        use candid_parser::utils::{service_equal, CandidSource};
+        let did = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("repute_backend.did");
+        service_equal(CandidSource::Text(&__export_service()), CandidSource::File(&did))
+            .unwrap_or_else(|e| panic!("repute_backend.did is out of date: {e}"));
+    }
+
+    #[test]
+    fn adjustments_stay_within_bounds() {
+        assert_eq!(adjusted_level(40, 10, 0, 100), 50);
+        assert_eq!(adjusted_level(95, 10, 0, 100), 100);
+        assert_eq!(adjusted_level(5, -20, 0, 100), 0);
+        assert_eq!(adjusted_level(30, -20, 20, 100), 20);
+        // levels set above the cap (or below the floor) by the admin are kept
+        assert_eq!(adjusted_level(120, 5, 0, 100), 120);
+        assert_eq!(adjusted_level(10, -5, 20, 100), 10);
+    }
+
+    #[test]
+    fn baseline_snapshot_migrates_to_current_version() {
+        let st: State =
+            icroots_state::restore_snapshot(include_bytes!("../snapshots/state_v0.bin")).unwrap();
+
+        let p = |i: u8| Principal::from_slice(&[i]);
+        assert_eq!((st.admin, st.event_bus), (p(0xad), Some(p(0xeb))));
+        assert!(st.allowed_setters.contains(&p(0xaa)));
+        assert_eq!((get_level(p(1)), get_level(p(2))), (15, 3));
+        assert!(get_history(p(1)).is_empty());
+
+        // pre_upgrade + post_upgrade: only the small State goes through the cell
+        STATE.with(|s| *s.borrow_mut() = st);
+        pre_upgrade();
+        let saved = SAVED_STATE.with(|c| c.borrow().get().clone());
+        assert_eq!(saved.restore::<State>().unwrap().admin, p(0xad));
+        assert_eq!(get_level(p(1)), 15);
+    }
+}
@@ -1,8 +1,12 @@
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::storage::{stable_restore, stable_save};
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query};
+use icroots_state::{decode, encode, Versioned};
 use std::cell::RefCell;
 
+/// Persisted layout (see `icroots_state`).
+/// 0: the first deployed `State`; 1: `review_amount` and `review_term_days` in `Config`
+const STATE_VERSION: u32 = 1;
+
 /// Configurable thresholds (tunable at deploy/init time)
 #[derive(CandidType, Deserialize, Clone, Debug)]
 struct Config {
@@ -12,6 +16,10 @@ struct Config {
     min_trust: u64,
     /// Upper bound used to clamp incoming trust scores (prevents weird inputs)
     trust_cap: u64,
+    /// Requested amounts above this are never approved outright
+    review_amount: u128,
+    /// Nor are terms longer than this
+    review_term_days: u32,
 }
 
 impl Default for Config {
@@ -20,6 +28,8 @@ impl Default for Config {
             min_collateral: 100_000, // demo default
             min_trust: 50,           // demo default
             trust_cap: 100,
+            review_amount: 1_000_000, // demo default
+            review_term_days: 180,
         }
     }
 }
@@ -29,17 +39,32 @@ struct InitArgs {
     min_collateral: Option<u128>,
     min_trust: Option<u64>,
     trust_cap: Option<u64>,
+    review_amount: Option<u128>,
+    review_term_days: Option<u32>,
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
 struct State {
     cfg: Config,
 }
 
-impl Default for State {
-    fn default() -> Self {
-        Self { cfg: Config::default() }
-    }
+/// Version 0 `Config`, as `stable_save`d by the first deployed build
+#[derive(CandidType, Deserialize)]
+struct ConfigV0 {
+    min_collateral: u128,
+    min_trust: u64,
+    trust_cap: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct StateV0 {
+    cfg: ConfigV0,
+}
+
+/// v0 → v1: the new review limits start at their defaults
+fn migrate_v0(old: StateV0) -> State {
+    let ConfigV0 { min_collateral, min_trust, trust_cap } = old.cfg;
+    State { cfg: Config { min_collateral, min_trust, trust_cap, ..Config::default() } }
 }
 
 thread_local! {
@@ -54,21 +79,33 @@ fn init(args: Option<InitArgs>) {
         if let Some(v) = args.min_collateral { st.cfg.min_collateral = v; }
         if let Some(v) = args.min_trust { st.cfg.min_trust = v; }
         if let Some(v) = args.trust_cap { st.cfg.trust_cap = v; }
+        if let Some(v) = args.review_amount { st.cfg.review_amount = v; }
+        if let Some(v) = args.review_term_days { st.cfg.review_term_days = v; }
     });
 }
 
 #[pre_upgrade]
 fn pre_upgrade() {
-    let st = STATE.with(|s| s.borrow().clone());
-    stable_save((st,)).expect("stable_save failed");
+    STATE.with(|s| icroots_state::save_to_stable_memory(&*s.borrow()));
 }
 
 #[post_upgrade]
 fn post_upgrade() {
-    let (st,): (State,) = stable_restore().unwrap_or((State::default(),));
+    let st = icroots_state::restore_from_stable_memory();
     STATE.with(|s| *s.borrow_mut() = st);
 }
 
+impl Versioned for State {
+    const VERSION: u32 = STATE_VERSION;
+
+    fn migrate_step(from: u32, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        match from {
+            0 => encode(&migrate_v0(decode(bytes)?)),
+            _ => Err(format!("no migration from state version {from}")),
+        }
+    }
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug)]
 struct Recommendation {
     /// "APPROVE" | "REVIEW" | "REJECT"
@@ -82,12 +119,18 @@ struct Recommendation {
 /// Deterministic recommendation using inputs + config.
 /// - `collateral`: u128 amount in smallest units
 /// - `trust`: u64 score (we clamp to `trust_cap`)
+/// - `amount` / `term_days`: the requested loan; large or long ones go to REVIEW
+///   (optional so older callers still decode)
 #[query]
-fn recommend(_p: Principal, collateral: u128, trust: u64) -> Recommendation {
-    let (min_collateral, min_trust, trust_cap) = STATE.with(|s| {
-        let c = &s.borrow().cfg;
-        (c.min_collateral, c.min_trust, c.trust_cap)
-    });
+fn recommend(
+    _p: Principal,
+    collateral: u128,
+    trust: u64,
+    amount: Option<u128>,
+    term_days: Option<u32>,
+) -> Recommendation {
+    let cfg = STATE.with(|s| s.borrow().cfg.clone());
+    let (min_collateral, min_trust, trust_cap) = (cfg.min_collateral, cfg.min_trust, cfg.trust_cap);
 
     let t = trust.min(trust_cap);
     // Ratios clamped to [0, 1] (integer math)
@@ -110,7 +153,25 @@ fn recommend(_p: Principal, collateral: u128, trust: u64) -> Recommendation {
         reasons.push(format!("trust {} ≥ min_trust {}", t, min_trust));
     }
 
-    let decision = if collateral >= min_collateral && t >= min_trust {
+    let mut within_limits = true;
+    if let Some(amount) = amount {
+        if amount > cfg.review_amount {
+            within_limits = false;
+            reasons.push(format!("amount {} > review_amount {}", amount, cfg.review_amount));
+        } else {
+            reasons.push(format!("amount {} ≤ review_amount {}", amount, cfg.review_amount));
+        }
+    }
+    if let Some(term) = term_days {
+        if term > cfg.review_term_days {
+            within_limits = false;
+            reasons.push(format!("term {} days > review_term_days {}", term, cfg.review_term_days));
+        } else {
+            reasons.push(format!("term {} days ≤ review_term_days {}", term, cfg.review_term_days));
+        }
+    }
+
+    let decision = if collateral >= min_collateral && t >= min_trust && within_limits {
         "APPROVE"
     } else if collateral >= (min_collateral / 2) || t >= min_trust {
         "REVIEW"
@@ -120,3 +181,30 @@ fn recommend(_p: Principal, collateral: u128, trust: u64) -> Recommendation {
 
     Recommendation { decision, score, reasons }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn baseline_snapshot_migrates_to_current_version() {
+        let st: State =
+            icroots_state::restore_snapshot(include_bytes!("../snapshots/state_v0.bin")).unwrap();
+
+        assert_eq!((st.cfg.min_collateral, st.cfg.min_trust, st.cfg.trust_cap), (250_000, 40, 90));
+        assert_eq!(st.cfg.review_amount, Config::default().review_amount);
+    }
+
+    #[test]
+    fn large_or_long_loans_go_to_review() {
+        let p = Principal::anonymous();
+        let small = recommend(p, 200_000, 80, Some(10_000), Some(30));
+        assert_eq!(small.decision, "APPROVE");
+        let large = recommend(p, 200_000, 80, Some(5_000_000), Some(30));
+        assert_eq!(large.decision, "REVIEW");
+        assert!(large.reasons.iter().any(|r| r.starts_with("amount 5000000 >")));
+        assert_eq!(recommend(p, 200_000, 80, Some(10_000), Some(365)).decision, "REVIEW");
+        // callers that don't send the loan are judged on collateral and trust alone
+        assert_eq!(recommend(p, 200_000, 80, None, None).decision, "APPROVE");
+    }
+}
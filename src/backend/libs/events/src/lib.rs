@@ -0,0 +1,200 @@
+//! Audit event schema shared by the ICRoots canisters and `event_bus_backend`.
+
+use candid::{CandidType, Deserialize, Principal};
+use icroots_state::candid_storable;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    /// Who triggered it: a user, an admin or another canister
+    pub actor: Principal,
+    /// Who it is about, e.g. the borrower or collateral owner
+    pub subject: Option<Principal>,
+    pub payload: Payload,
+    pub timestamp_ns: u64,
+    /// Canister that emitted it; `event_bus_backend` overwrites it with the caller
+    pub emitter: Principal,
+}
+
+impl Event {
+    /// Stamped with the current time and this canister as emitter
+    pub fn new(
+        kind: EventKind,
+        actor: Principal,
+        subject: Option<Principal>,
+        payload: Payload,
+    ) -> Self {
+        Self {
+            kind,
+            actor,
+            subject,
+            payload,
+            timestamp_ns: ic_cdk::api::time(),
+            emitter: ic_cdk::id(),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    LoanRequested,
+    LoanRepaid,
+    LoanOverdue,
+    LoanDefaulted,
+    LoanLiquidated,
+    ApplicationApproved,
+    ApplicationRejected,
+    LoansConfigChanged,
+    CollateralDeposited,
+    CollateralWithdrawn,
+    CollateralLocked,
+    CollateralReleased,
+    CollateralSeized,
+    LevelChanged,
+}
+
+impl EventKind {
+    /// Every kind; append new ones at the end (`event_bus_backend` indexes
+    /// kinds by their position here)
+    pub const ALL: [EventKind; 14] = [
+        Self::LoanRequested,
+        Self::LoanRepaid,
+        Self::LoanOverdue,
+        Self::LoanDefaulted,
+        Self::LoanLiquidated,
+        Self::ApplicationApproved,
+        Self::ApplicationRejected,
+        Self::LoansConfigChanged,
+        Self::CollateralDeposited,
+        Self::CollateralWithdrawn,
+        Self::CollateralLocked,
+        Self::CollateralReleased,
+        Self::CollateralSeized,
+        Self::LevelChanged,
+    ];
+
+    /// Dotted name, prefixed by the emitting domain (e.g. "loans.repay")
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::LoanRequested => "loans.request",
+            Self::LoanRepaid => "loans.repay",
+            Self::LoanOverdue => "loans.overdue",
+            Self::LoanDefaulted => "loans.default",
+            Self::LoanLiquidated => "loans.liquidate",
+            Self::ApplicationApproved => "loans.application.approve",
+            Self::ApplicationRejected => "loans.application.reject",
+            Self::LoansConfigChanged => "loans.config",
+            Self::CollateralDeposited => "collateral.deposit_mock",
+            Self::CollateralWithdrawn => "collateral.withdraw",
+            Self::CollateralLocked => "collateral.lock",
+            Self::CollateralReleased => "collateral.release",
+            Self::CollateralSeized => "collateral.seize",
+            Self::LevelChanged => "repute.set_level",
+        }
+    }
+}
+
+/// Domain-specific details, one variant per emitting canister
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Payload {
+    Loans(LoansEvent),
+    Collateral(CollateralEvent),
+    Repute(ReputeEvent),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum LoansEvent {
+    Requested {
+        amount: u128,
+        term_days: u32,
+        installments: u32,
+        /// "APPROVE" | "REVIEW" | "REJECT"
+        decision: String,
+        score: u64,
+        reasons: Vec<String>,
+        loan_id: Option<u128>,
+        application_id: Option<u128>,
+    },
+    Repaid {
+        loan_id: u128,
+        amount: u128,
+        applied: u128,
+        excess: u128,
+        credited: u128,
+    },
+    /// Overdue or defaulted by the sweep
+    StatusChanged { loan_id: u128, days_past_due: u64 },
+    Liquidated {
+        loan_id: u128,
+        debt: u128,
+        seized: u128,
+        returned: u128,
+        recipient: Principal,
+    },
+    ApplicationDecided {
+        application_id: u128,
+        amount: u128,
+        loan_id: Option<u128>,
+        reason: Option<String>,
+    },
+    ConfigChanged {
+        field: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum CollateralEvent {
+    Deposited { amount: u128 },
+    Withdrawn { amount: u128 },
+    Locked { loan_id: u128, amount: u128 },
+    Released { loan_id: u128, amount: u128 },
+    Seized {
+        loan_id: u128,
+        to: Principal,
+        seized: u128,
+        returned: u128,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ReputeEvent {
+    LevelChanged {
+        previous: u64,
+        level: u64,
+        reason: Option<String>,
+    },
+}
+
+candid_storable!(Event);
+
+/// Best-effort: a failing or missing bus never fails the caller
+pub async fn emit(bus: Principal, event: Event) {
+    let _: Result<(), _> = ic_cdk::call(bus, "emit", (event,)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::Storable;
+
+    #[test]
+    fn stored_events_round_trip() {
+        let event = Event {
+            kind: EventKind::CollateralSeized,
+            actor: Principal::from_slice(&[1]),
+            subject: Some(Principal::from_slice(&[2])),
+            payload: Payload::Collateral(CollateralEvent::Seized {
+                loan_id: 7,
+                to: Principal::from_slice(&[3]),
+                seized: 100,
+                returned: 5,
+            }),
+            timestamp_ns: 42,
+            emitter: Principal::from_slice(&[4]),
+        };
+        assert_eq!(Event::from_bytes(event.to_bytes()), event);
+        assert_eq!(event.kind.name(), "collateral.seize");
+    }
+}
@@ -0,0 +1,206 @@
+//! Versioned `State` persistence shared by the ICRoots canisters.
+//!
+//! Each canister saves its heap `State` as a [`Persisted`] value tagged with
+//! the layout version it was written at, and on upgrade walks the bytes up to
+//! its current version one [`Versioned::migrate_step`] at a time.
+//!
+//! Version 0 is the baseline: the single `State` the first deployed build
+//! `stable_save`d at offset 0 of stable memory. Bump `VERSION` and add a
+//! `migrate_step` arm only when a layout that has actually been deployed
+//! changes shape; layouts that never reached a canister need no step.
+//!
+//! `pre_upgrade` saves with [`save`] (or [`save_to_stable_memory`]) and
+//! `post_upgrade` restores with [`restore_or_trap`] (or
+//! [`restore_from_stable_memory`]). Restoring checks for the baseline bytes
+//! first, before a `MemoryManager` or `StableCell` claims stable memory and
+//! writes its own header over them, and traps rather than starting over with
+//! an empty `State` when the saved one can't be restored.
+
+use candid::de::IDLDeserialize;
+use candid::{CandidType, Deserialize};
+use ic_cdk::api::stable::{stable_bytes, stable_read, stable_size};
+use ic_cdk::trap;
+use ic_stable_structures::{DefaultMemoryImpl, Memory, StableCell};
+use serde::de::DeserializeOwned;
+use std::cell::RefCell;
+
+// re-exported for `candid_storable!`
+#[doc(hidden)]
+pub use candid;
+#[doc(hidden)]
+pub use ic_stable_structures;
+
+/// Implement `Storable` (unbounded, Candid-encoded) for the given types
+#[macro_export]
+macro_rules! candid_storable {
+    ($($t:ty),*) => {$(
+        impl $crate::ic_stable_structures::Storable for $t {
+            fn to_bytes(&self) -> ::std::borrow::Cow<'_, [u8]> {
+                ::std::borrow::Cow::Owned($crate::candid::encode_one(self).expect("candid encode"))
+            }
+            fn from_bytes(bytes: ::std::borrow::Cow<[u8]>) -> Self {
+                $crate::candid::decode_one(bytes.as_ref()).expect("candid decode")
+            }
+            const BOUND: $crate::ic_stable_structures::storable::Bound =
+                $crate::ic_stable_structures::storable::Bound::Unbounded;
+        }
+    )*};
+}
+
+/// A canister `State` whose persisted layout is versioned
+pub trait Versioned: CandidType + DeserializeOwned {
+    /// Layout written by this build
+    const VERSION: u32;
+
+    /// Decode state saved at version `from` and re-encode it as version `from + 1`
+    fn migrate_step(from: u32, bytes: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+/// `State` tagged with the layout version it was saved at
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Persisted {
+    pub version: u32,
+    /// Candid-encoded `State` of that version
+    pub state: Vec<u8>,
+}
+
+impl Persisted {
+    pub fn new<S: Versioned>(st: &S) -> Self {
+        Self { version: S::VERSION, state: encode(st).expect("candid encode") }
+    }
+
+    /// Bring the saved state up to `S::VERSION`
+    pub fn restore<S: Versioned>(self) -> Result<S, String> {
+        restore(self.version, self.state)
+    }
+}
+
+candid_storable!(Persisted);
+
+/// Bring `State` saved at `version` up to `S::VERSION`, one step at a time
+pub fn restore<S: Versioned>(mut version: u32, mut bytes: Vec<u8>) -> Result<S, String> {
+    if version > S::VERSION {
+        return Err(format!("unknown state version {version}"));
+    }
+    while version < S::VERSION {
+        bytes = S::migrate_step(version, &bytes)?;
+        version += 1;
+    }
+    decode(&bytes)
+}
+
+/// Decode one Candid value; `stable_save` leaves the rest of stable memory
+/// zeroed, so trailing bytes are ignored.
+pub fn decode<T: DeserializeOwned + CandidType>(bytes: &[u8]) -> Result<T, String> {
+    IDLDeserialize::new(bytes)
+        .and_then(|mut de| de.get_value())
+        .map_err(|e| format!("undecodable state: {e}"))
+}
+
+pub fn encode<T: CandidType>(value: &T) -> Result<Vec<u8>, String> {
+    candid::encode_one(value).map_err(|e| e.to_string())
+}
+
+/// Save `st` into the canister's state cell
+pub fn save<S: Versioned, M: Memory>(cell: &RefCell<StableCell<Persisted, M>>, st: &S) {
+    cell.borrow_mut().set(Persisted::new(st)).expect("save state");
+}
+
+/// The baseline `State` if stable memory still holds it, otherwise what
+/// `saved` reads back, brought up to `S::VERSION`
+pub fn restore_or_trap<S: Versioned>(saved: impl FnOnce() -> Persisted) -> S {
+    baseline_state()
+        .unwrap_or_else(saved)
+        .restore()
+        .unwrap_or_else(|e| trap(&format!("cannot restore state: {e}")))
+}
+
+/// [`save`] for canisters without a memory manager: the state cell spans all
+/// of stable memory
+pub fn save_to_stable_memory<S: Versioned>(st: &S) {
+    // `new` overwrites whatever layout the previous version left behind
+    StableCell::new(DefaultMemoryImpl::default(), Persisted::new(st)).expect("save state");
+}
+
+/// [`restore_or_trap`] for state saved by [`save_to_stable_memory`]
+pub fn restore_from_stable_memory<S: Versioned + Default>() -> S {
+    restore_or_trap(|| {
+        match StableCell::init(DefaultMemoryImpl::default(), Persisted::new(&S::default())) {
+            Ok(cell) => cell.get().clone(),
+            Err(e) => trap(&format!("cannot read saved state: {e:?}")),
+        }
+    })
+}
+
+/// What the baseline build `stable_save`d, if stable memory still holds it
+fn baseline_state() -> Option<Persisted> {
+    has_baseline_layout().then(|| Persisted { version: 0, state: stable_bytes() })
+}
+
+/// `stable_save` wrote Candid ("DIDL") from offset 0; the memory manager and
+/// `StableCell` write their own "MGR"/"SCL" headers there instead.
+fn has_baseline_layout() -> bool {
+    let mut magic = [0u8; 4];
+    if stable_size() > 0 {
+        stable_read(0, &mut magic);
+    }
+    &magic == b"DIDL"
+}
+
+/// Restore a baseline `stable_save` snapshot the way `post_upgrade` would
+/// find it: zero-padded to a whole Wasm page. For the canisters' snapshot tests.
+pub fn restore_snapshot<S: Versioned>(snapshot: &[u8]) -> Result<S, String> {
+    if !snapshot.starts_with(b"DIDL") {
+        return Err("not a stable_save snapshot".into());
+    }
+    let mut bytes = snapshot.to_vec();
+    bytes.resize(bytes.len().next_multiple_of(1 << 16), 0);
+    restore(0, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::Storable;
+
+    #[derive(CandidType, Deserialize)]
+    struct CounterV0 {
+        count: u32,
+    }
+
+    #[derive(CandidType, Deserialize, Debug, PartialEq)]
+    struct Counter {
+        count: u64,
+        label: String,
+    }
+
+    impl Versioned for Counter {
+        const VERSION: u32 = 1;
+
+        fn migrate_step(from: u32, bytes: &[u8]) -> Result<Vec<u8>, String> {
+            match from {
+                0 => {
+                    let old: CounterV0 = decode(bytes)?;
+                    encode(&Counter { count: old.count.into(), label: "v0".into() })
+                }
+                _ => Err(format!("no migration from state version {from}")),
+            }
+        }
+    }
+
+    #[test]
+    fn saved_state_migrates_up_to_the_current_version() {
+        let v0 = encode(&CounterV0 { count: 7 }).unwrap();
+        let st: Counter = restore_snapshot(&v0).unwrap();
+        assert_eq!(st, Counter { count: 7, label: "v0".into() });
+
+        let saved = Persisted::from_bytes(Persisted::new(&st).to_bytes());
+        assert_eq!(saved.version, 1);
+        assert_eq!(saved.restore::<Counter>().unwrap(), st);
+
+        assert!(restore::<Counter>(2, Vec::new()).is_err());
+        assert!(restore_snapshot::<Counter>(&[0; 16]).is_err());
+        let err = restore::<Counter>(1, vec![0; 16]).err().unwrap();
+        assert!(err.starts_with("undecodable state"));
+    }
+}
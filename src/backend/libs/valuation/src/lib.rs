@@ -0,0 +1,66 @@
+//! Collateral valuation shared by `loans_backend` and `collateral_backend`,
+//! and the Candid records they and `oracle_backend` exchange about it.
+
+use candid::{CandidType, Deserialize};
+
+pub const SATS_PER_BTC: u128 = 100_000_000;
+pub const BPS_DENOM: u128 = 10_000;
+
+/// Latest price, as returned by `oracle_backend.get_price`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PriceStatus {
+    pub price_e6: u64,
+    pub timestamp_ns: u64,
+    pub age_secs: u64,
+    /// true once the sample is older than `max_age_secs`
+    pub stale: bool,
+}
+
+/// Converts collateral units (sats) to loan units (USD, 6 decimals)
+#[derive(Clone, Copy, Debug)]
+pub struct Valuation {
+    /// USD per BTC (6 decimals); None values collateral 1:1 (no oracle configured)
+    pub price_e6: Option<u64>,
+}
+
+impl Valuation {
+    pub fn value_of(&self, units: u128) -> u128 {
+        match self.price_e6 {
+            Some(p) => units.saturating_mul(p as u128) / SATS_PER_BTC,
+            None => units,
+        }
+    }
+
+    /// Collateral units needed to be worth `value` (rounded up)
+    pub fn units_for(&self, value: u128) -> u128 {
+        match self.price_e6 {
+            Some(p) => value.saturating_mul(SATS_PER_BTC).div_ceil((p as u128).max(1)),
+            None => value,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LoanDebt {
+    pub loan_id: u128,
+    /// Principal and interest due now
+    pub debt: u128,
+}
+
+/// What a borrower's open loans need from their collateral, as returned by
+/// `loans_backend.get_collateral_needs`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CollateralNeeds {
+    pub debts: Vec<LoanDebt>,
+    /// A loan is healthy while its locked value × this / debt ≥ 10_000
+    pub liquidation_threshold_bps: u32,
+}
+
+impl CollateralNeeds {
+    /// Units a loan owing `debt` needs at `valuation` to stay at the
+    /// liquidation threshold
+    pub fn units_needed(&self, debt: u128, valuation: Valuation) -> u128 {
+        let threshold_bps = (self.liquidation_threshold_bps as u128).max(1);
+        valuation.units_for(debt.saturating_mul(BPS_DENOM).div_ceil(threshold_bps))
+    }
+}
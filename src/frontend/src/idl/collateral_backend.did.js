@@ -2,17 +2,84 @@ export const idlFactory = ({ IDL }) => {
   const InitArgs = IDL.Record({
     event_bus: IDL.Opt(IDL.Principal),
     admin: IDL.Opt(IDL.Principal),
+    oracle: IDL.Opt(IDL.Principal),
     loans: IDL.Opt(IDL.Principal),
   });
+  const Error = IDL.Variant({
+    InvalidAmount: IDL.Text,
+    AlreadyLocked: IDL.Null,
+    InsufficientCollateral: IDL.Record({
+      free: IDL.Nat,
+      locked: IDL.Nat,
+    }),
+    LockNotFound: IDL.Null,
+    DependencyUnavailable: IDL.Text,
+    Unauthorized: IDL.Text,
+    ReservedForLoans: IDL.Record({ free: IDL.Nat, reserved: IDL.Nat }),
+  });
+  const CollateralAccount = IDL.Record({
+    free: IDL.Nat,
+    locked: IDL.Nat,
+  });
+  const PriceStatus = IDL.Record({
+    timestamp_ns: IDL.Nat64,
+    stale: IDL.Bool,
+    age_secs: IDL.Nat64,
+    price_e6: IDL.Nat64,
+  });
+  const CollateralValuation = IDL.Record({
+    locked_value: IDL.Opt(IDL.Nat),
+    free: IDL.Nat,
+    locked: IDL.Nat,
+    free_value: IDL.Opt(IDL.Nat),
+    price: IDL.Opt(PriceStatus),
+  });
+  const SeizeResult = IDL.Record({ seized: IDL.Nat, returned: IDL.Nat });
   return IDL.Service({
-    deposit_mock: IDL.Func([IDL.Principal, IDL.Nat], [], []),
+    deposit_mock: IDL.Func(
+      [IDL.Principal, IDL.Nat],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    get_account: IDL.Func([IDL.Principal], [CollateralAccount], ["query"]),
     get_collateral: IDL.Func([IDL.Principal], [IDL.Nat], ["query"]),
+    get_valuation: IDL.Func(
+      [IDL.Principal],
+      [IDL.Variant({ Ok: CollateralValuation, Err: Error })],
+      [],
+    ),
+    lock: IDL.Func(
+      [IDL.Principal, IDL.Nat, IDL.Nat],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    release: IDL.Func(
+      [IDL.Nat],
+      [IDL.Variant({ Ok: IDL.Nat, Err: Error })],
+      [],
+    ),
+    seize: IDL.Func(
+      [IDL.Nat, IDL.Nat, IDL.Principal],
+      [IDL.Variant({ Ok: SeizeResult, Err: Error })],
+      [],
+    ),
+    set_loans: IDL.Func(
+      [IDL.Principal],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    withdraw: IDL.Func(
+      [IDL.Nat],
+      [IDL.Variant({ Ok: CollateralAccount, Err: Error })],
+      [],
+    ),
   });
 };
 export const init = ({ IDL }) => {
   const InitArgs = IDL.Record({
     event_bus: IDL.Opt(IDL.Principal),
     admin: IDL.Opt(IDL.Principal),
+    oracle: IDL.Opt(IDL.Principal),
     loans: IDL.Opt(IDL.Principal),
   });
   return [IDL.Opt(InitArgs)];
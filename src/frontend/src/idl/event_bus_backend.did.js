@@ -1,10 +1,170 @@
 export const idlFactory = ({ IDL }) => {
-  const Event = IDL.Text;
+  const InitArgs = IDL.Record({
+    repute: IDL.Opt(IDL.Principal),
+    admin: IDL.Opt(IDL.Principal),
+    collateral: IDL.Opt(IDL.Principal),
+    loans: IDL.Opt(IDL.Principal),
+    max_events: IDL.Opt(IDL.Nat64),
+    trust_ai: IDL.Opt(IDL.Principal),
+  });
+  const Error = IDL.Variant({
+    Unauthorized: IDL.Text,
+    InvalidArgument: IDL.Text,
+  });
+  const EventKind = IDL.Variant({
+    LoansConfigChanged: IDL.Null,
+    CollateralLocked: IDL.Null,
+    CollateralSeized: IDL.Null,
+    LoanDefaulted: IDL.Null,
+    LoanRepaid: IDL.Null,
+    CollateralReleased: IDL.Null,
+    LoanRequested: IDL.Null,
+    CollateralWithdrawn: IDL.Null,
+    ApplicationApproved: IDL.Null,
+    LoanOverdue: IDL.Null,
+    CollateralDeposited: IDL.Null,
+    ApplicationRejected: IDL.Null,
+    LevelChanged: IDL.Null,
+    LoanLiquidated: IDL.Null,
+  });
+  const LoansEvent = IDL.Variant({
+    StatusChanged: IDL.Record({
+      loan_id: IDL.Nat,
+      days_past_due: IDL.Nat64,
+    }),
+    Repaid: IDL.Record({
+      loan_id: IDL.Nat,
+      applied: IDL.Nat,
+      excess: IDL.Nat,
+      amount: IDL.Nat,
+      credited: IDL.Nat,
+    }),
+    Liquidated: IDL.Record({
+      loan_id: IDL.Nat,
+      debt: IDL.Nat,
+      recipient: IDL.Principal,
+      seized: IDL.Nat,
+      returned: IDL.Nat,
+    }),
+    ApplicationDecided: IDL.Record({
+      loan_id: IDL.Opt(IDL.Nat),
+      application_id: IDL.Nat,
+      amount: IDL.Nat,
+      reason: IDL.Opt(IDL.Text),
+    }),
+    Requested: IDL.Record({
+      reasons: IDL.Vec(IDL.Text),
+      loan_id: IDL.Opt(IDL.Nat),
+      decision: IDL.Text,
+      term_days: IDL.Nat32,
+      score: IDL.Nat64,
+      application_id: IDL.Opt(IDL.Nat),
+      installments: IDL.Nat32,
+      amount: IDL.Nat,
+    }),
+    ConfigChanged: IDL.Record({
+      new: IDL.Opt(IDL.Text),
+      old: IDL.Opt(IDL.Text),
+      field: IDL.Text,
+    }),
+  });
+  const ReputeEvent = IDL.Variant({
+    LevelChanged: IDL.Record({
+      previous: IDL.Nat64,
+      level: IDL.Nat64,
+      reason: IDL.Opt(IDL.Text),
+    }),
+  });
+  const CollateralEvent = IDL.Variant({
+    Released: IDL.Record({ loan_id: IDL.Nat, amount: IDL.Nat }),
+    Withdrawn: IDL.Record({ amount: IDL.Nat }),
+    Locked: IDL.Record({ loan_id: IDL.Nat, amount: IDL.Nat }),
+    Seized: IDL.Record({
+      to: IDL.Principal,
+      loan_id: IDL.Nat,
+      seized: IDL.Nat,
+      returned: IDL.Nat,
+    }),
+    Deposited: IDL.Record({ amount: IDL.Nat }),
+  });
+  const Payload = IDL.Variant({
+    Loans: LoansEvent,
+    Repute: ReputeEvent,
+    Collateral: CollateralEvent,
+  });
+  const Event = IDL.Record({
+    actor: IDL.Principal,
+    timestamp_ns: IDL.Nat64,
+    subject: IDL.Opt(IDL.Principal),
+    kind: EventKind,
+    emitter: IDL.Principal,
+    payload: Payload,
+  });
+  const EventFilter = IDL.Record({
+    to_ns: IDL.Opt(IDL.Nat64),
+    actor: IDL.Opt(IDL.Principal),
+    subject: IDL.Opt(IDL.Principal),
+    from_ns: IDL.Opt(IDL.Nat64),
+    kind_prefix: IDL.Opt(IDL.Text),
+  });
+  const LoggedEvent = IDL.Record({
+    seq: IDL.Nat64,
+    received_ns: IDL.Nat64,
+    event: Event,
+  });
+  const EventPage = IDL.Record({
+    next_seq: IDL.Opt(IDL.Nat64),
+    events: IDL.Vec(LoggedEvent),
+  });
+  const Config = IDL.Record({ max_events: IDL.Nat64 });
+  const Stats = IDL.Record({
+    next_seq: IDL.Nat64,
+    first_seq: IDL.Opt(IDL.Nat64),
+    rejected_emits: IDL.Nat64,
+    events: IDL.Nat64,
+  });
   return IDL.Service({
-    emit: IDL.Func([Event], [], []),
-    list_recent: IDL.Func([IDL.Nat64], [IDL.Vec(Event)], ["query"]),
+    add_emitter: IDL.Func(
+      [IDL.Principal],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    emit: IDL.Func([Event], [IDL.Variant({ Ok: IDL.Null, Err: Error })], []),
+    find_events: IDL.Func(
+      [EventFilter, IDL.Nat64, IDL.Nat64],
+      [EventPage],
+      ["query"],
+    ),
+    get_config: IDL.Func([], [Config], ["query"]),
+    get_events: IDL.Func(
+      [IDL.Nat64, IDL.Nat64],
+      [IDL.Vec(LoggedEvent)],
+      ["query"],
+    ),
+    get_stats: IDL.Func([], [Stats], ["query"]),
+    list_emitters: IDL.Func([], [IDL.Vec(IDL.Principal)], ["query"]),
+    list_recent: IDL.Func([IDL.Nat64], [IDL.Vec(LoggedEvent)], ["query"]),
+    list_since: IDL.Func([IDL.Nat64], [IDL.Vec(LoggedEvent)], ["query"]),
+    remove_emitter: IDL.Func(
+      [IDL.Principal],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    set_max_events: IDL.Func(
+      [IDL.Nat64],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
   });
 };
 export const init = ({ IDL }) => {
-  return [];
+  const InitArgs = IDL.Record({
+    repute: IDL.Opt(IDL.Principal),
+    admin: IDL.Opt(IDL.Principal),
+    collateral: IDL.Opt(IDL.Principal),
+    loans: IDL.Opt(IDL.Principal),
+    max_events: IDL.Opt(IDL.Nat64),
+    trust_ai: IDL.Opt(IDL.Principal),
+  });
+  return [IDL.Opt(InitArgs)];
 };
@@ -1,49 +1,371 @@
 export const idlFactory = ({ IDL }) => {
+  const LtvTier = IDL.Record({
+    min_level: IDL.Nat64,
+    max_ltv_bps: IDL.Nat32,
+  });
+  const OverpaymentPolicy = IDL.Variant({
+    Cap: IDL.Null,
+    Reject: IDL.Null,
+    Credit: IDL.Null,
+  });
   const InitArgs = IDL.Record({
     event_bus: IDL.Opt(IDL.Principal),
     repute: IDL.Opt(IDL.Principal),
+    default_grace_days: IDL.Opt(IDL.Nat32),
     admin: IDL.Opt(IDL.Principal),
+    ltv_tiers: IDL.Opt(IDL.Vec(LtvTier)),
+    dedup_window_secs: IDL.Opt(IDL.Nat64),
+    liquidation_penalty_bps: IDL.Opt(IDL.Nat32),
+    dependency_retries: IDL.Opt(IDL.Nat32),
+    oracle: IDL.Opt(IDL.Principal),
+    rep_level_cap: IDL.Opt(IDL.Nat64),
     collateral: IDL.Opt(IDL.Principal),
+    overdue_grace_days: IDL.Opt(IDL.Nat32),
+    apr_bps: IDL.Opt(IDL.Nat32),
+    default_term_days: IDL.Opt(IDL.Nat32),
+    dedup_drift_secs: IDL.Opt(IDL.Nat64),
+    max_term_days: IDL.Opt(IDL.Nat32),
+    ledger: IDL.Opt(IDL.Principal),
+    rep_late_step: IDL.Opt(IDL.Nat64),
+    rep_level_floor: IDL.Opt(IDL.Nat64),
+    overpayment_policy: IDL.Opt(OverpaymentPolicy),
+    rep_default_step: IDL.Opt(IDL.Nat64),
+    liquidators: IDL.Opt(IDL.Vec(IDL.Principal)),
     trust_ai: IDL.Opt(IDL.Principal),
+    treasury_subaccount: IDL.Opt(IDL.Vec(IDL.Nat8)),
+    liquidation_threshold_bps: IDL.Opt(IDL.Nat32),
+    underwriters: IDL.Opt(IDL.Vec(IDL.Principal)),
+    rep_on_time_step: IDL.Opt(IDL.Nat64),
+  });
+  const Error = IDL.Variant({
+    NotRegistered: IDL.Null,
+    InvalidAmount: IDL.Text,
+    PriceUnavailable: IDL.Text,
+    InsufficientCollateral: IDL.Record({
+      free: IDL.Nat,
+      required: IDL.Nat,
+    }),
+    LoanNotFound: IDL.Null,
+    CallInProgress: IDL.Null,
+    DependencyUnavailable: IDL.Text,
+    ApplicationNotFound: IDL.Null,
+    Unauthorized: IDL.Text,
+    CreatedInFuture: IDL.Record({ canister_time: IDL.Nat64 }),
+    InvalidArgument: IDL.Text,
+    TooOld: IDL.Null,
+    TransferFailed: IDL.Text,
+    InvalidState: IDL.Text,
+  });
+  const ApplicationStatus = IDL.Variant({
+    Approved: IDL.Record({ loan_id: IDL.Nat }),
+    Rejected: IDL.Record({ reason: IDL.Text }),
+    Approving: IDL.Null,
+    Pending: IDL.Null,
+  });
+  const Amortization = IDL.Variant({
+    Annuity: IDL.Null,
+    EqualPrincipal: IDL.Null,
+  });
+  const RepaymentPlan = IDL.Record({
+    style: Amortization,
+    installments: IDL.Nat32,
+  });
+  const Application = IDL.Record({
+    id: IDL.Nat,
+    status: ApplicationStatus,
+    reasons: IDL.Vec(IDL.Text),
+    term_days: IDL.Nat32,
+    decided_at_ns: IDL.Opt(IDL.Nat64),
+    plan: RepaymentPlan,
+    borrower: IDL.Principal,
+    score: IDL.Nat64,
+    created_at_ns: IDL.Nat64,
+    amount: IDL.Nat,
+    decided_by: IDL.Opt(IDL.Principal),
+  });
+  const LoanDebt = IDL.Record({ loan_id: IDL.Nat, debt: IDL.Nat });
+  const CollateralNeeds = IDL.Record({
+    liquidation_threshold_bps: IDL.Nat32,
+    debts: IDL.Vec(LoanDebt),
+  });
+  const Config = IDL.Record({
+    default_grace_days: IDL.Nat32,
+    ltv_tiers: IDL.Vec(LtvTier),
+    dedup_window_secs: IDL.Nat64,
+    liquidation_penalty_bps: IDL.Nat32,
+    dependency_retries: IDL.Nat32,
+    rep_level_cap: IDL.Nat64,
+    overdue_grace_days: IDL.Nat32,
+    apr_bps: IDL.Nat32,
+    default_term_days: IDL.Nat32,
+    dedup_drift_secs: IDL.Nat64,
+    max_term_days: IDL.Nat32,
+    rep_late_step: IDL.Nat64,
+    rep_level_floor: IDL.Nat64,
+    overpayment_policy: OverpaymentPolicy,
+    rep_default_step: IDL.Nat64,
+    liquidation_threshold_bps: IDL.Nat32,
+    rep_on_time_step: IDL.Nat64,
+  });
+  const LoansConfig = IDL.Record({
+    event_bus: IDL.Opt(IDL.Principal),
+    repute: IDL.Principal,
+    admin: IDL.Principal,
+    oracle: IDL.Opt(IDL.Principal),
+    collateral: IDL.Principal,
+    ledger: IDL.Opt(IDL.Principal),
+    liquidators: IDL.Vec(IDL.Principal),
+    trust_ai: IDL.Principal,
+    treasury_subaccount: IDL.Opt(IDL.Vec(IDL.Nat8)),
+    underwriters: IDL.Vec(IDL.Principal),
+    params: Config,
+  });
+  const Repayment = IDL.Record({
+    block_index: IDL.Opt(IDL.Nat),
+    at_ns: IDL.Nat64,
+    amount: IDL.Nat,
+  });
+  const Installment = IDL.Record({
+    principal: IDL.Nat,
+    due_at_ns: IDL.Nat64,
+    interest: IDL.Nat,
+    principal_paid: IDL.Nat,
+    interest_paid: IDL.Nat,
+    index: IDL.Nat32,
+    paid_at_ns: IDL.Opt(IDL.Nat64),
+  });
+  const SummaryField = IDL.Variant({
+    Level: IDL.Null,
+    Collateral: IDL.Null,
+    CollateralValue: IDL.Null,
   });
   const LoanInfo = IDL.Record({
     id: IDL.Nat,
     status: IDL.Text,
+    disbursement_block: IDL.Opt(IDL.Nat),
+    due_at_ns: IDL.Nat64,
+    health_factor_bps: IDL.Opt(IDL.Nat),
+    interest_accrued: IDL.Nat,
+    apr_bps: IDL.Nat32,
+    total_due: IDL.Nat,
+    collateral_locked: IDL.Nat,
+    days_past_due: IDL.Nat64,
+    principal_outstanding: IDL.Nat,
+    next_due_at_ns: IDL.Nat64,
     amount: IDL.Nat,
   });
   const Summary = IDL.Record({
+    missing: IDL.Vec(SummaryField),
     outstanding: IDL.Nat,
-    collateral: IDL.Nat,
-    level: IDL.Nat64,
+    collateral: IDL.Opt(IDL.Nat),
+    interest_accrued: IDL.Nat,
+    level: IDL.Opt(IDL.Nat64),
     loans: IDL.Vec(LoanInfo),
+    stale: IDL.Vec(SummaryField),
+    principal_outstanding: IDL.Nat,
+    collateral_value: IDL.Opt(IDL.Nat),
     registered: IDL.Bool,
   });
+  const Liquidation = IDL.Record({
+    at_ns: IDL.Nat64,
+    debt: IDL.Nat,
+    recipient: IDL.Principal,
+    seized: IDL.Nat,
+    liquidator: IDL.Principal,
+    returned: IDL.Nat,
+  });
+  const Idempotency = IDL.Record({
+    key: IDL.Vec(IDL.Nat8),
+    created_at_time: IDL.Nat64,
+  });
   const RepayResult = IDL.Record({
     status: IDL.Text,
     repaid: IDL.Nat,
+    block_index: IDL.Opt(IDL.Nat),
+    principal_repaid: IDL.Nat,
+    interest_accrued: IDL.Nat,
+    credit_balance: IDL.Nat,
+    credit_used: IDL.Nat,
+    principal_outstanding: IDL.Nat,
+    interest_paid: IDL.Nat,
     remaining: IDL.Nat,
+    excess: IDL.Nat,
+    credited: IDL.Nat,
   });
   const LoanDecision = IDL.Record({
     reasons: IDL.Vec(IDL.Text),
     loan_id: IDL.Opt(IDL.Nat),
     decision: IDL.Text,
     score: IDL.Nat64,
+    application_id: IDL.Opt(IDL.Nat),
   });
   return IDL.Service({
+    add_liquidator: IDL.Func(
+      [IDL.Principal],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    add_underwriter: IDL.Func(
+      [IDL.Principal],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    approve_application: IDL.Func(
+      [IDL.Nat],
+      [IDL.Variant({ Ok: IDL.Nat, Err: Error })],
+      [],
+    ),
+    get_application: IDL.Func(
+      [IDL.Nat],
+      [IDL.Variant({ Ok: Application, Err: Error })],
+      ["query"],
+    ),
+    get_collateral_needs: IDL.Func(
+      [IDL.Principal],
+      [CollateralNeeds],
+      ["query"],
+    ),
+    get_config: IDL.Func([], [LoansConfig], ["query"]),
+    get_credit: IDL.Func([IDL.Principal], [IDL.Nat], ["query"]),
+    get_repayments: IDL.Func(
+      [IDL.Nat],
+      [IDL.Variant({ Ok: IDL.Vec(Repayment), Err: Error })],
+      ["query"],
+    ),
+    get_schedule: IDL.Func(
+      [IDL.Nat],
+      [IDL.Variant({ Ok: IDL.Vec(Installment), Err: Error })],
+      ["query"],
+    ),
     get_summary: IDL.Func([IDL.Principal], [Summary], []),
+    liquidate: IDL.Func(
+      [IDL.Nat],
+      [IDL.Variant({ Ok: Liquidation, Err: Error })],
+      [],
+    ),
+    list_applications: IDL.Func(
+      [IDL.Bool],
+      [IDL.Variant({ Ok: IDL.Vec(Application), Err: Error })],
+      ["query"],
+    ),
+    my_applications: IDL.Func([], [IDL.Vec(Application)], ["query"]),
     ping: IDL.Func([], [IDL.Text], ["query"]),
     register_user: IDL.Func([], [], []),
-    repay: IDL.Func([IDL.Nat, IDL.Nat], [RepayResult], []),
-    request_loan: IDL.Func([IDL.Nat], [LoanDecision], []),
+    reject_application: IDL.Func(
+      [IDL.Nat, IDL.Text],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    remove_liquidator: IDL.Func(
+      [IDL.Principal],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    remove_underwriter: IDL.Func(
+      [IDL.Principal],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    repay: IDL.Func(
+      [IDL.Nat, IDL.Nat, IDL.Opt(Idempotency)],
+      [IDL.Variant({ Ok: RepayResult, Err: Error })],
+      [],
+    ),
+    request_loan: IDL.Func(
+      [
+        IDL.Nat,
+        IDL.Opt(IDL.Nat32),
+        IDL.Opt(RepaymentPlan),
+        IDL.Opt(Idempotency),
+      ],
+      [IDL.Variant({ Ok: LoanDecision, Err: Error })],
+      [],
+    ),
+    set_admin: IDL.Func(
+      [IDL.Principal],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    set_collateral: IDL.Func(
+      [IDL.Principal],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    set_config: IDL.Func(
+      [Config],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    set_event_bus: IDL.Func(
+      [IDL.Opt(IDL.Principal)],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    set_ledger: IDL.Func(
+      [IDL.Opt(IDL.Principal)],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    set_oracle: IDL.Func(
+      [IDL.Opt(IDL.Principal)],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    set_overpayment_policy: IDL.Func(
+      [OverpaymentPolicy],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    set_repute: IDL.Func(
+      [IDL.Principal],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
+    set_trust_ai: IDL.Func(
+      [IDL.Principal],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
   });
 };
 export const init = ({ IDL }) => {
+  const LtvTier = IDL.Record({
+    min_level: IDL.Nat64,
+    max_ltv_bps: IDL.Nat32,
+  });
+  const OverpaymentPolicy = IDL.Variant({
+    Cap: IDL.Null,
+    Reject: IDL.Null,
+    Credit: IDL.Null,
+  });
   const InitArgs = IDL.Record({
     event_bus: IDL.Opt(IDL.Principal),
     repute: IDL.Opt(IDL.Principal),
+    default_grace_days: IDL.Opt(IDL.Nat32),
     admin: IDL.Opt(IDL.Principal),
+    ltv_tiers: IDL.Opt(IDL.Vec(LtvTier)),
+    dedup_window_secs: IDL.Opt(IDL.Nat64),
+    liquidation_penalty_bps: IDL.Opt(IDL.Nat32),
+    dependency_retries: IDL.Opt(IDL.Nat32),
+    oracle: IDL.Opt(IDL.Principal),
+    rep_level_cap: IDL.Opt(IDL.Nat64),
     collateral: IDL.Opt(IDL.Principal),
+    overdue_grace_days: IDL.Opt(IDL.Nat32),
+    apr_bps: IDL.Opt(IDL.Nat32),
+    default_term_days: IDL.Opt(IDL.Nat32),
+    dedup_drift_secs: IDL.Opt(IDL.Nat64),
+    max_term_days: IDL.Opt(IDL.Nat32),
+    ledger: IDL.Opt(IDL.Principal),
+    rep_late_step: IDL.Opt(IDL.Nat64),
+    rep_level_floor: IDL.Opt(IDL.Nat64),
+    overpayment_policy: IDL.Opt(OverpaymentPolicy),
+    rep_default_step: IDL.Opt(IDL.Nat64),
+    liquidators: IDL.Opt(IDL.Vec(IDL.Principal)),
     trust_ai: IDL.Opt(IDL.Principal),
+    treasury_subaccount: IDL.Opt(IDL.Vec(IDL.Nat8)),
+    liquidation_threshold_bps: IDL.Opt(IDL.Nat32),
+    underwriters: IDL.Opt(IDL.Vec(IDL.Principal)),
+    rep_on_time_step: IDL.Opt(IDL.Nat64),
   });
   return [IDL.Opt(InitArgs)];
 };
@@ -4,9 +4,30 @@ export const idlFactory = ({ IDL }) => {
     admin: IDL.Opt(IDL.Principal),
     loans: IDL.Opt(IDL.Principal),
   });
+  const Error = IDL.Variant({
+    Unauthorized: IDL.Text,
+    InvalidArgument: IDL.Text,
+  });
+  const LevelChange = IDL.Record({
+    actor: IDL.Principal,
+    previous: IDL.Nat64,
+    at_ns: IDL.Nat64,
+    level: IDL.Nat64,
+    reason: IDL.Opt(IDL.Text),
+  });
   return IDL.Service({
-    get_level: IDL.Func([IDL.Principal], [IDL.Nat], ["query"]),
-    set_level: IDL.Func([IDL.Principal, IDL.Nat], [], []),
+    adjust_level: IDL.Func(
+      [IDL.Principal, IDL.Int64, IDL.Nat64, IDL.Nat64, IDL.Text],
+      [IDL.Variant({ Ok: IDL.Nat64, Err: Error })],
+      [],
+    ),
+    get_history: IDL.Func([IDL.Principal], [IDL.Vec(LevelChange)], ["query"]),
+    get_level: IDL.Func([IDL.Principal], [IDL.Nat64], ["query"]),
+    set_level: IDL.Func(
+      [IDL.Principal, IDL.Nat64, IDL.Opt(IDL.Text)],
+      [IDL.Variant({ Ok: IDL.Null, Err: Error })],
+      [],
+    ),
   });
 };
 export const init = ({ IDL }) => {
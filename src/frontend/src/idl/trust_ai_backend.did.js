@@ -2,6 +2,8 @@ export const idlFactory = ({ IDL }) => {
   const InitArgs = IDL.Record({
     trust_cap: IDL.Opt(IDL.Nat64),
     min_trust: IDL.Opt(IDL.Nat64),
+    review_term_days: IDL.Opt(IDL.Nat32),
+    review_amount: IDL.Opt(IDL.Nat),
     min_collateral: IDL.Opt(IDL.Nat),
   });
   const Recommendation = IDL.Record({
@@ -11,7 +13,7 @@ export const idlFactory = ({ IDL }) => {
   });
   return IDL.Service({
     recommend: IDL.Func(
-      [IDL.Principal, IDL.Nat, IDL.Nat64],
+      [IDL.Principal, IDL.Nat, IDL.Nat64, IDL.Opt(IDL.Nat), IDL.Opt(IDL.Nat32)],
       [Recommendation],
       ["query"],
     ),
@@ -21,6 +23,8 @@ export const init = ({ IDL }) => {
   const InitArgs = IDL.Record({
     trust_cap: IDL.Opt(IDL.Nat64),
     min_trust: IDL.Opt(IDL.Nat64),
+    review_term_days: IDL.Opt(IDL.Nat32),
+    review_amount: IDL.Opt(IDL.Nat),
     min_collateral: IDL.Opt(IDL.Nat),
   });
   return [IDL.Opt(InitArgs)];
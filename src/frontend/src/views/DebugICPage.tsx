@@ -13,15 +13,16 @@ import { Principal } from "@dfinity/principal";
  * - `dfx generate` created declarations at src/declarations/*
  * - All 5 canisters are deployed to a local replica (http://127.0.0.1:4943)
  * - Candid types:
- *   • event_bus.list_recent: (nat64) -> (vec text)
- *   • repute.get_level: (principal) -> (nat) query
- *   • collateral.deposit_mock: (principal, nat) -> ()
- *   • trust_ai.recommend: (principal, nat, nat64) -> (record { decision : text; score : nat64; reasons : vec text })
+ *   • event_bus.emit: (Event) -> (variant { Ok; Err : Error })
+ *   • event_bus.list_recent: (nat64) -> (vec LoggedEvent)
+ *   • repute.get_level: (principal) -> (nat64) query
+ *   • collateral.deposit_mock: (principal, nat) -> (variant { Ok; Err : Error })
+ *   • trust_ai.recommend: (principal, nat, nat64, opt nat, opt nat32) -> (Recommendation)
  *   • loans.ping: () -> (text)
  *   • loans.register_user: () -> ()
  *   • loans.get_summary: (principal) -> (Summary)
- *   • loans.request_loan: (nat) -> (LoanDecision)
- *   • loans.repay: (nat, nat) -> (RepayResult)
+ *   • loans.request_loan: (nat, opt nat32, opt RepaymentPlan, opt Idempotency) -> (variant { Ok : LoanDecision; Err : Error })
+ *   • loans.repay: (nat, nat, opt Idempotency) -> (variant { Ok : RepayResult; Err : Error })
  */
 
 // Declarations (generated by `dfx generate`)
@@ -118,6 +119,11 @@ function pretty(v: unknown): string {
   }
 }
 
+// Candid `variant { Ok : T; Err : Error }` results
+function prettyResult(r: { Ok: unknown } | { Err: unknown }): string {
+  return "Err" in r ? "ERR: " + pretty(r.Err) : pretty(r.Ok);
+}
+
 export default function DebugICPage() {
   // Replica host (default local)
   const [host, setHost] = useState("http://127.0.0.1:4943");
@@ -215,12 +221,36 @@ export default function DebugICPage() {
         <div className="grid gap-6 md:grid-cols-2">
           {/* Event Bus */}
           <Card title={`event_bus_backend  (id: ${EVENT_BUS_ID})`}>
-            <Field label="Event text" value={eventMsg} onChange={setEventMsg} />
+            <Field
+              label="Event reason (text)"
+              value={eventMsg}
+              onChange={setEventMsg}
+            />
             <SectionActions>
               <Btn
                 onClick={async () => {
                   try {
-                    await eventBus.emit(eventMsg);
+                    // a repute LevelChanged event carrying the text as its reason
+                    const r = await eventBus.emit({
+                      kind: { LevelChanged: null },
+                      actor: principal,
+                      subject: [principal],
+                      payload: {
+                        Repute: {
+                          LevelChanged: {
+                            previous: 0n,
+                            level: 0n,
+                            reason: [eventMsg],
+                          },
+                        },
+                      },
+                      timestamp_ns: BigInt(Date.now()) * 1000000n,
+                      emitter: principal, // the bus stamps the real caller
+                    });
+                    if ("Err" in r) {
+                      setEventsOut(prettyResult(r));
+                      return;
+                    }
                     const list = await eventBus.list_recent(10n); // nat64
                     setEventsOut(pretty(list));
                   } catch (e) {
@@ -278,10 +308,14 @@ export default function DebugICPage() {
               <Btn
                 onClick={async () => {
                   try {
-                    await collateral.deposit_mock(
+                    const r = await collateral.deposit_mock(
                       principal,
                       BigInt(depositAmt || "0"),
                     );
+                    if ("Err" in r) {
+                      setCollOut(prettyResult(r));
+                      return;
+                    }
                     const bal = await collateral.get_collateral(principal);
                     setCollOut(pretty(bal));
                   } catch (e) {
@@ -332,6 +366,8 @@ export default function DebugICPage() {
                       principal,
                       BigInt(taCollateral || "0"),
                       BigInt(taTrust || "0"), // nat64
+                      [], // review amount
+                      [], // review term
                     );
                     setTaOut(pretty(rec));
                   } catch (e) {
@@ -385,7 +421,8 @@ export default function DebugICPage() {
                 onClick={async () => {
                   try {
                     const s = await loans.get_summary(principal);
-                    setSummaryOut(pretty(s));
+                    // level is opt nat64: [] when repute could not be reached
+                    setSummaryOut(pretty({ ...s, level: s.level[0] ?? null }));
                   } catch (e) {
                     setSummaryOut("ERROR: " + String(e));
                   }
@@ -396,8 +433,13 @@ export default function DebugICPage() {
               <Btn
                 onClick={async () => {
                   try {
-                    const d = await loans.request_loan(BigInt(loanAmt || "0"));
-                    setSummaryOut(pretty(d));
+                    const d = await loans.request_loan(
+                      BigInt(loanAmt || "0"),
+                      [], // term_days
+                      [], // plan
+                      [], // idempotency
+                    );
+                    setSummaryOut(prettyResult(d));
                   } catch (e) {
                     setSummaryOut("ERROR: " + String(e));
                   }
@@ -430,8 +472,9 @@ export default function DebugICPage() {
                     const r = await loans.repay(
                       BigInt(repayLoanId || "0"),
                       BigInt(repayAmt || "0"),
+                      [], // idempotency
                     );
-                    setRepayOut(pretty(r));
+                    setRepayOut(prettyResult(r));
                   } catch (e) {
                     setRepayOut("ERROR: " + String(e));
                   }